@@ -1,8 +1,33 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct CLi {
     #[arg(short, long)]
     pub path: String,
+
+    /// Rebuild every repo even if its build cache is up to date
+    #[arg(long)]
+    pub force_rebuild: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// Undo a completed release using its run report
+    Rollback {
+        /// Run id of the report to roll back
+        #[arg(long)]
+        run: String,
+        /// Only show what would be undone
+        #[arg(long)]
+        preview: bool,
+        /// Reset the branch instead of reverting the release commit
+        #[arg(long)]
+        reset: bool,
+    },
+    /// Clone missing repos and fetch the existing ones
+    Sync,
 }
@@ -0,0 +1,396 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::TimeoutsConfig;
+
+// How often a running command is checked for its timeout and Ctrl-C
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// Time a command gets to exit after SIGTERM before it is killed
+const KILL_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+// Where the output of the commands started by the current stage goes and how
+// long they may take. Set up by `main` for a release run; without it commands
+// run as before.
+struct LogState {
+    dir: PathBuf,
+    stream: bool,
+    repo: Option<String>,
+    stage: String,
+    files: Vec<(String, PathBuf)>,
+    command_timeout: Option<Duration>,
+    stage_timeouts: HashMap<String, Duration>,
+    deadline: Option<Instant>,
+}
+
+static STATE: Mutex<Option<LogState>> = Mutex::new(None);
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+// Logs of the run go to `<dir>/<run-id>/<repo>/<stage>.log`
+pub fn init(dir: &str, run_id: &str, stream: bool) {
+    *get_state() = Some(LogState {
+        dir: Path::new(dir).join(run_id),
+        stream,
+        repo: None,
+        stage: String::from("run"),
+        files: Vec::new(),
+        command_timeout: None,
+        stage_timeouts: HashMap::new(),
+        deadline: None,
+    });
+}
+
+// Limits apply from now on, the run limit counts from here
+pub fn set_timeouts(config: &TimeoutsConfig) {
+    if let Some(state) = get_state().as_mut() {
+        state.command_timeout = config.command.map(Duration::from_secs);
+        state.stage_timeouts = config
+            .stages
+            .iter()
+            .map(|(stage, secs)| (stage.clone(), Duration::from_secs(*secs)))
+            .collect();
+        state.deadline = config
+            .run
+            .map(|secs| Instant::now() + Duration::from_secs(secs));
+    }
+}
+
+// Commands started without a repo are logged to `<dir>/<run-id>/<stage>.log`
+pub fn set_stage(repo: Option<&str>, stage: &str) {
+    if let Some(state) = get_state().as_mut() {
+        state.repo = repo.map(String::from);
+        state.stage = stage.to_string();
+    }
+}
+
+// Log files written for the repo so far, in the order they were created
+pub fn get_files(repo: &str) -> Vec<String> {
+    match get_state().as_ref() {
+        Some(state) => state
+            .files
+            .iter()
+            .filter(|(file_repo, _)| file_repo == repo)
+            .map(|(_, path)| path.to_string_lossy().to_string())
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+// Ctrl-C was pressed. Running commands are killed and no new ones start.
+pub fn cancel() {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+// Ctrl-C was pressed or the run is over its time limit
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+        || get_state()
+            .as_ref()
+            .and_then(|state| state.deadline)
+            .is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+// Lets the clean up of a cancelled run start commands again
+pub fn reset_cancel() {
+    CANCELLED.store(false, Ordering::SeqCst);
+    if let Some(state) = get_state().as_mut() {
+        state.deadline = None;
+    }
+}
+
+pub trait LoggedCommand {
+    // Same as `Command::output`, but the output is also written to the stage log
+    // and streamed to the terminal when configured. The command is killed
+    // on timeout and on Ctrl-C.
+    fn logged_output(&mut self) -> io::Result<Output>;
+
+    // Same limits as `logged_output` for commands whose output must stay out of the logs
+    fn timed_output(&mut self) -> io::Result<Output>;
+}
+
+impl LoggedCommand for Command {
+    fn logged_output(&mut self) -> io::Result<Output> {
+        match get_log_file() {
+            Some(log_file) => {
+                let (path, stream) = log_file?;
+                run_logged(self, &path, stream, get_timeout())
+            }
+            None => self.output(),
+        }
+    }
+
+    fn timed_output(&mut self) -> io::Result<Output> {
+        if get_state().is_none() {
+            return self.output();
+        }
+
+        run(self, None, false, get_timeout())
+    }
+}
+
+fn run_logged(
+    command: &mut Command,
+    path: &Path,
+    stream: bool,
+    timeout: Option<Duration>,
+) -> io::Result<Output> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "$ {}", get_command_line(command))?;
+    if let Some(dir) = command.get_current_dir() {
+        writeln!(file, "# in {}", dir.display())?;
+    }
+
+    let file = Arc::new(Mutex::new(file));
+    let output = run(command, Some(&file), stream, timeout);
+    match &output {
+        Ok(output) => writeln!(lock(&file), "# {}\n", output.status)?,
+        Err(err) => writeln!(lock(&file), "# {}\n", err)?,
+    }
+
+    output
+}
+
+// Runs the command in its own session, so a timeout or Ctrl-C can take down everything
+// it started and Ctrl-C in the terminal only reaches the version updater. The session has
+// no controlling terminal: a prompt that reads /dev/tty fails right away instead of being
+// stopped by SIGTTIN, and git and ssh are told not to prompt at all.
+fn run(
+    command: &mut Command,
+    file: Option<&Arc<Mutex<File>>>,
+    stream: bool,
+    timeout: Option<Duration>,
+) -> io::Result<Output> {
+    if is_cancelled() {
+        return Err(io::Error::new(
+            io::ErrorKind::Interrupted,
+            format!("Run cancelled before: {}", get_command_line(command)),
+        ));
+    }
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .env("GIT_TERMINAL_PROMPT", "0");
+    if std::env::var_os("GIT_SSH_COMMAND").is_none() {
+        command.env("GIT_SSH_COMMAND", "ssh -o BatchMode=yes");
+    }
+    // SAFETY: `setsid` is async-signal-safe and the closure allocates nothing
+    unsafe {
+        command.pre_exec(|| match libc::setsid() {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        });
+    }
+    let mut child = command.spawn()?;
+
+    let stdout = child.stdout.take().map(|pipe| {
+        let file = file.cloned();
+        let terminal = stream.then(io::stdout);
+        thread::spawn(move || copy_stream(pipe, terminal, file))
+    });
+    let stderr = child.stderr.take().map(|pipe| {
+        let file = file.cloned();
+        let terminal = stream.then(io::stderr);
+        thread::spawn(move || copy_stream(pipe, terminal, file))
+    });
+
+    // Readers of a killed command are left behind, its pipes may be held open by
+    // processes that escaped the group
+    let status = wait(&mut child, timeout).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("{}: {}", err, get_command_line(command)),
+        )
+    })?;
+    let join = |handle: Option<thread::JoinHandle<io::Result<Vec<u8>>>>| match handle {
+        Some(handle) => handle
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("Output reader panicked"))),
+        None => Ok(Vec::new()),
+    };
+
+    Ok(Output {
+        status,
+        stdout: join(stdout)?,
+        stderr: join(stderr)?,
+    })
+}
+
+fn wait(child: &mut Child, timeout: Option<Duration>) -> io::Result<ExitStatus> {
+    let started_at = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if is_cancelled() {
+            kill(child)?;
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "Command cancelled",
+            ));
+        }
+        if let Some(timeout) = timeout.filter(|timeout| started_at.elapsed() >= *timeout) {
+            kill(child)?;
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("Command timed out after {}s", timeout.as_secs()),
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// Asks the process group to stop and kills it after the grace period
+fn kill(child: &mut Child) -> io::Result<()> {
+    let group = -(child.id() as libc::pid_t);
+    // SAFETY: `kill` only sends a signal, the child leads the group since `setsid`
+    unsafe { libc::kill(group, libc::SIGTERM) };
+    let started_at = Instant::now();
+    while child.try_wait()?.is_none() && started_at.elapsed() < KILL_GRACE_PERIOD {
+        thread::sleep(POLL_INTERVAL);
+    }
+    // Whatever is left of the group ignored SIGTERM
+    unsafe { libc::kill(group, libc::SIGKILL) };
+    child.wait()?;
+
+    Ok(())
+}
+
+fn get_state() -> MutexGuard<'static, Option<LogState>> {
+    STATE.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn lock(file: &Mutex<File>) -> MutexGuard<'_, File> {
+    file.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+// Stage limit, or the default one for every command
+fn get_timeout() -> Option<Duration> {
+    let state = get_state();
+    let state = state.as_ref()?;
+    state
+        .stage_timeouts
+        .get(&state.stage)
+        .copied()
+        .or(state.command_timeout)
+}
+
+// Creates the stage log on first use and remembers it for the report
+fn get_log_file() -> Option<io::Result<(PathBuf, bool)>> {
+    let mut state = get_state();
+    let state = state.as_mut()?;
+    let dir = match &state.repo {
+        Some(repo) => state.dir.join(repo),
+        None => state.dir.clone(),
+    };
+    let path = dir.join(format!("{}.log", state.stage));
+    if let Some(repo) = &state.repo {
+        if !state.files.iter().any(|(_, file)| *file == path) {
+            state.files.push((repo.clone(), path.clone()));
+        }
+    }
+    if let Err(err) = fs::create_dir_all(&dir) {
+        return Some(Err(err));
+    }
+
+    Some(Ok((path, state.stream)))
+}
+
+fn get_command_line(command: &Command) -> String {
+    let mut line = command.get_program().to_string_lossy().to_string();
+    for arg in command.get_args() {
+        line.push(' ');
+        line.push_str(&arg.to_string_lossy());
+    }
+
+    line
+}
+
+// Collects the output of the command while copying it to the terminal and the log
+fn copy_stream(
+    mut pipe: impl Read,
+    mut terminal: Option<impl Write>,
+    file: Option<Arc<Mutex<File>>>,
+) -> io::Result<Vec<u8>> {
+    let mut captured = Vec::new();
+    let mut buffer = [0; 8192];
+    loop {
+        let read = pipe.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        if let Some(terminal) = terminal.as_mut() {
+            terminal.write_all(&buffer[..read])?;
+        }
+        if let Some(file) = &file {
+            lock(file).write_all(&buffer[..read])?;
+        }
+        captured.extend_from_slice(&buffer[..read]);
+    }
+
+    Ok(captured)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_command_line_joins_program_and_args() {
+        let mut command = Command::new("npm");
+        command.arg("run").arg("build");
+
+        assert_eq!(get_command_line(&command), "npm run build");
+    }
+
+    #[test]
+    fn test_run_logged_appends_command_and_output() {
+        let path =
+            std::env::temp_dir().join(format!("version_updater_log_{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("echo built; echo warning >&2");
+
+        let output = run_logged(&mut command, &path, false, None).unwrap();
+
+        let log = fs::read_to_string(&path).unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "built\n");
+        assert!(log
+            .contains("$ sh -c echo built; echo warning >&2\nbuilt\nwarning\n# exit status: 0\n"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_run_kills_command_on_timeout() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("sleep 30 & sleep 30");
+        let started_at = Instant::now();
+
+        let result = run(&mut command, None, false, Some(Duration::from_millis(200)));
+
+        assert!(matches!(result, Err(err) if err.kind() == io::ErrorKind::TimedOut));
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_run_does_not_wait_for_terminal_prompts() {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg("echo $GIT_TERMINAL_PROMPT; read answer < /dev/tty");
+        let started_at = Instant::now();
+
+        let output = run(&mut command, None, false, Some(Duration::from_secs(10))).unwrap();
+
+        assert!(!output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "0\n");
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+    }
+}
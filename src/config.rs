@@ -1,5 +1,6 @@
 use serde_derive::Deserialize;
 
+use std::collections::HashMap;
 use std::fs;
 
 use crate::{
@@ -18,6 +19,8 @@ pub struct GitConfig {
     pub version: Option<String>,
     pub branch: String,
     pub release_branch: String,
+    // Tag, branch or commit to create `release_branch` from when it is missing
+    pub release_branch_base: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -31,6 +34,304 @@ pub struct AwsConfig {
 pub struct LoggerConfig {
     pub log_level: LogLevel,
 }
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CheckoutPolicy {
+    Refuse,
+    #[default]
+    StashAndRestore,
+    Force,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChangelogFormat {
+    #[default]
+    KeepAChangelog,
+    Custom,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangelogConfig {
+    pub enabled: bool,
+    #[serde(default = "default_changelog_path")]
+    pub path: String,
+    #[serde(default)]
+    pub format: ChangelogFormat,
+    // Used with `Custom` format. Supports `{version}`, `{date}` and `{entries}` placeholders.
+    pub template: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DependenciesConfig {
+    pub propagate: bool,
+    pub release_consumers: bool,
+    // Seconds to wait for a released package to show up in the registry before its
+    // consumers are bumped. 0 does not wait.
+    #[serde(default = "default_publish_timeout")]
+    pub publish_timeout: u64,
+}
+
+fn default_publish_timeout() -> u64 {
+    900
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackMergeMode {
+    #[default]
+    Merge,
+    PullRequest,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BackMergeConfig {
+    pub enabled: bool,
+    #[serde(default)]
+    pub mode: BackMergeMode,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WorktreeConfig {
+    pub enabled: bool,
+    // Defaults to a `version_updater` folder in the system temp dir
+    pub root: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CloneConfig {
+    // Supports the `{name}` placeholder, e.g. `ssh://git-codecommit.us-east-1.amazonaws.com/v1/repos/{name}`
+    pub remote_url_template: String,
+    // Shallow clone depth. The history is deepened until the last release tag and the
+    // merge-base with the release branch are reachable, and unshallowed as a last resort.
+    pub depth: Option<u32>,
+    #[serde(default)]
+    pub partial: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TicketsConfig {
+    pub enabled: bool,
+    // Regexes matched against commit subjects and bodies, e.g. `\b(CONF-\d+)\b`.
+    // Branch names are read from the subjects of the merges that brought a commit in.
+    pub patterns: Vec<String>,
+    // Supports the `{ticket}` placeholder, e.g. `https://jira.example.com/browse/{ticket}`
+    pub url_template: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReleaseNotesFormat {
+    #[default]
+    Markdown,
+    Json,
+    Html,
+    Plain,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReleaseNotesConfig {
+    pub enabled: bool,
+    #[serde(default = "default_release_notes_formats")]
+    pub formats: Vec<ReleaseNotesFormat>,
+    // Notes of every run are written here as `<run-id>.<ext>`
+    #[serde(default = "default_release_notes_dir")]
+    pub dir: String,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HistoryMode {
+    // Commits since the last release tag
+    #[default]
+    Range,
+    // Commits on `branch` that have no patch-id equivalent on `release_branch`
+    Cherry,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct HistoryConfig {
+    #[serde(default)]
+    pub mode: HistoryMode,
+    // Follow only the first parent of merges, so merged-in branches are listed as one commit
+    #[serde(default)]
+    pub first_parent: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReleaseRulesConfig {
+    pub enabled: bool,
+    // Files matching these globs don't need a release, e.g. `**/*.md` or `.github/**`
+    #[serde(default)]
+    pub ignore_paths: Vec<String>,
+    // Conventional Commit types that don't need a release, e.g. `docs` or `ci`
+    #[serde(default)]
+    pub ignore_types: Vec<String>,
+}
+
+// Shell commands run before and after each stage. A failing command stops the repo.
+#[derive(Debug, Deserialize, Default)]
+pub struct HooksConfig {
+    #[serde(default)]
+    pub pre_checkout: Vec<String>,
+    #[serde(default)]
+    pub post_checkout: Vec<String>,
+    #[serde(default)]
+    pub pre_rebuild: Vec<String>,
+    #[serde(default)]
+    pub post_rebuild: Vec<String>,
+    #[serde(default)]
+    pub pre_patch: Vec<String>,
+    #[serde(default)]
+    pub post_patch: Vec<String>,
+    #[serde(default)]
+    pub pre_push: Vec<String>,
+    #[serde(default)]
+    pub post_push: Vec<String>,
+    #[serde(default)]
+    pub pre_pr: Vec<String>,
+    #[serde(default)]
+    pub post_pr: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct RepoSettings {
+    // Path globs relative to the repo root. Only commits touching `include` and not only
+    // `exclude` count as changes. Empty means the whole repo. The scope applies to the
+    // repo as a whole, sub-packages of a monorepo are not released on their own.
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    // Paths or globs removed before the rebuild. Node repos default to `node_modules` and `dist`.
+    pub clean: Option<Vec<String>>,
+    // Quality gate commands used instead of the ones of the repo type
+    pub gates: Option<Vec<String>>,
+    // Files the package has to contain, used instead of the ones of the repo type
+    pub package_files: Option<Vec<String>>,
+    // Run after the global hooks of the same stage
+    #[serde(default)]
+    pub hooks: HooksConfig,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GatesConfig {
+    pub enabled: bool,
+    // Shell commands run in the repo after the rebuild, e.g. `npm test`
+    #[serde(default)]
+    pub node: Vec<String>,
+    #[serde(default)]
+    pub python: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyConfig {
+    pub enabled: bool,
+    // Paths or globs inside the package, like `dist/*.js`
+    #[serde(default)]
+    pub node_files: Vec<String>,
+    #[serde(default)]
+    pub python_files: Vec<String>,
+    // Allowed growth over the last released package of the repo
+    pub max_growth_percent: Option<f64>,
+    // Last released package of each repo, one `<repo>.json` per repo
+    #[serde(default = "default_verify_dir")]
+    pub dir: String,
+}
+
+fn default_verify_dir() -> String {
+    "packages".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BuildCacheConfig {
+    pub enabled: bool,
+    // Fingerprints of the last successful rebuilds, one `<repo>.json` per repo
+    #[serde(default = "default_build_cache_dir")]
+    pub dir: String,
+}
+
+fn default_build_cache_dir() -> String {
+    "build-cache".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PythonConfig {
+    #[serde(default = "default_python_interpreter")]
+    pub interpreter: String,
+    // Virtualenvs are created here as `<venv_root>/<repo>`. Defaults to the system temp dir.
+    pub venv_root: Option<String>,
+    #[serde(default)]
+    pub run_tests: bool,
+    #[serde(default)]
+    pub compile_check: bool,
+}
+
+impl Default for PythonConfig {
+    fn default() -> Self {
+        Self {
+            interpreter: default_python_interpreter(),
+            venv_root: None,
+            run_tests: false,
+            compile_check: false,
+        }
+    }
+}
+
+fn default_python_interpreter() -> String {
+    "python3".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogsConfig {
+    // Command output goes to `<dir>/<run-id>/<repo>/<stage>.log`
+    #[serde(default = "default_logs_dir")]
+    pub dir: String,
+    // Also print the command output while it is produced
+    #[serde(default)]
+    pub stream: bool,
+}
+
+impl Default for LogsConfig {
+    fn default() -> Self {
+        Self {
+            dir: default_logs_dir(),
+            stream: false,
+        }
+    }
+}
+
+fn default_logs_dir() -> String {
+    "logs".to_string()
+}
+
+// All limits are in seconds, a command over its limit is killed with its process group
+#[derive(Debug, Default, Deserialize)]
+pub struct TimeoutsConfig {
+    // Limit of the whole run. Once it is over, the run stops like on Ctrl-C.
+    pub run: Option<u64>,
+    // Default limit of every command
+    pub command: Option<u64>,
+    // Limits of the commands of a stage, like `rebuild` or `login`
+    #[serde(default)]
+    pub stages: HashMap<String, u64>,
+}
+
+fn default_preflight() -> bool {
+    true
+}
+
+fn default_report_dir() -> String {
+    "reports".to_string()
+}
+
+fn default_changelog_path() -> String {
+    "CHANGELOG.md".to_string()
+}
+
+fn default_release_notes_formats() -> Vec<ReleaseNotesFormat> {
+    vec![ReleaseNotesFormat::Markdown]
+}
+
+fn default_release_notes_dir() -> String {
+    "release-notes".to_string()
+}
 // Top level struct to hold the TOML data.
 #[derive(Debug, Deserialize)]
 pub struct Data {
@@ -41,8 +342,38 @@ pub struct Data {
     pub repo_rebuild_required: bool,
     pub process_only_updated_repo: bool,
     pub disable_checks: bool,
+    #[serde(default)]
+    pub checkout_policy: CheckoutPolicy,
+    #[serde(default = "default_preflight")]
+    pub preflight: bool,
+    #[serde(default = "default_report_dir")]
+    pub report_dir: String,
+    #[serde(default)]
+    pub logs: LogsConfig,
+    #[serde(default)]
+    pub timeouts: TimeoutsConfig,
     pub repos: WorkersConfig,
     pub logger: LoggerConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    pub changelog: Option<ChangelogConfig>,
+    pub dependencies: Option<DependenciesConfig>,
+    pub back_merge: Option<BackMergeConfig>,
+    pub worktree: Option<WorktreeConfig>,
+    pub clone: Option<CloneConfig>,
+    pub tickets: Option<TicketsConfig>,
+    pub release_notes: Option<ReleaseNotesConfig>,
+    pub release_rules: Option<ReleaseRulesConfig>,
+    #[serde(default)]
+    pub python: PythonConfig,
+    pub gates: Option<GatesConfig>,
+    pub build_cache: Option<BuildCacheConfig>,
+    pub verify: Option<VerifyConfig>,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    // Per repo settings keyed by the repo name from `repos`
+    #[serde(default)]
+    pub repo_settings: HashMap<String, RepoSettings>,
 }
 
 #[derive(Debug, Clone, Copy)]
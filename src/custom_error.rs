@@ -8,6 +8,12 @@ pub enum CustomError {
     ConfigParsingError(String),
     NpmConfigError(String),
     VersionBuild(String),
+    FileSystem(String),
+    DependencyGraph(String),
+    DirtyRepo(String),
+    Preflight(String),
+    Cancelled(String),
+    PackageVerification(String),
 }
 
 impl std::error::Error for CustomError {}
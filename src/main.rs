@@ -1,19 +1,43 @@
 use clap::Parser;
 use std::collections::HashMap;
 use std::path::Path;
+use std::time::Duration;
 mod cli;
+mod command_log;
 mod config;
 mod custom_error;
 mod logger;
+mod report;
+#[cfg(test)]
+mod test_utils;
 mod workers;
-use cli::CLi;
-use custom_error::CustomResult;
-use workers::branch::BranchSwitcher;
-use workers::history::HistoryProvider;
+use cli::{CLi, Commands};
+use config::ReleaseNotesFormat;
+use custom_error::{CustomError, CustomResult};
+use report::{RepoStatus, RunReport};
+use workers::back_merge::BackMerger;
+use workers::branch::{BranchSwitcher, RepoState};
+use workers::build_cache::BuildCache;
+use workers::cleaner::get_clean_targets;
+use workers::cloner::{sync, RepoCloner};
+use workers::dependencies::{get_commit_message, DependencyGraph, DependencyUpdater};
+use workers::gates::{get_failed_gates, get_gate_commands, GateRunner};
+use workers::history::{get_subjects_string, HistoryProvider};
+use workers::hooks::{Hook, HookContext, HookRunner};
 use workers::loginer::login;
-use workers::patcher::Patcher;
-use workers::rebuilder::RepoRebuilder;
+use workers::patcher::{PatchResult, Patcher};
+use workers::preflight::preflight;
+use workers::rebuilder::{get_build_output, RepoRebuilder};
+use workers::release_branch::ReleaseBranchCreator;
+use workers::release_notes::{
+    group_by_type, render as render_release_notes, render_changes, ReleaseNotesWriter,
+};
+use workers::release_rules::ReleaseRulesChecker;
+use workers::rollback::rollback;
+use workers::tickets::TicketExtractor;
+use workers::verifier::{get_required_files, PackageVerifier};
 use workers::version::VersionSelecter;
+use workers::worktree::{get_worktree_root, WorktreeManager};
 
 fn main() -> CustomResult<()> {
     println!("Reading cli args...");
@@ -25,25 +49,129 @@ fn main() -> CustomResult<()> {
     crate::logger::Logger::init(config.logger.log_level);
     let logger = crate::logger::Logger::new();
     logger.info("Version updater started!");
-    let repos = config.repos.get_repos_list()?;
-    logger.info(format!("Repos to update: {:#?}", repos).as_str());
 
-    let mut result_string = String::new();
-    result_string.push('\n');
+    if let Some(Commands::Rollback {
+        run,
+        preview,
+        reset,
+    }) = &cli_args.command
+    {
+        return rollback(&config, run, *preview, *reset);
+    }
+    if let Some(Commands::Sync) = &cli_args.command {
+        return sync(&config);
+    }
+
+    let mut report = RunReport::new(&config.git.branch, &config.git.release_branch);
+    command_log::init(&config.logs.dir, &report.run_id, config.logs.stream);
+    command_log::set_timeouts(&config.timeouts);
+    ctrlc::set_handler(|| {
+        crate::logger::Logger::new().warn("Cancelling the run...");
+        command_log::cancel();
+    })
+    .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+    let mut repos = config.repos.get_repos_list()?;
+
+    let mut clone_errors: HashMap<String, String> = HashMap::new();
+    if config.clone.is_some() {
+        for repo in repos.iter() {
+            if command_log::is_cancelled() {
+                break;
+            }
+            command_log::set_stage(Some(repo), "clone");
+            let cloner = RepoCloner {
+                root: &config.root,
+                repo_name: repo,
+                branch: &config.git.branch,
+                release_branch: &config.git.release_branch,
+                config: &config.clone,
+            };
+            if let Err(e) = cloner.ensure_present() {
+                logger.error(format!("Failed to clone repo: {}. {}", repo, e).as_str());
+                clone_errors.insert(repo.clone(), e.to_string());
+            }
+        }
+    }
+
+    let propagate_dependencies = config
+        .dependencies
+        .as_ref()
+        .is_some_and(|dependencies| dependencies.propagate);
+    let release_consumers = config
+        .dependencies
+        .as_ref()
+        .is_some_and(|dependencies| dependencies.release_consumers);
+    let dependency_graph = if propagate_dependencies {
+        logger.debug("Building dependency graph...");
+        let graph = DependencyGraph::build(&config.root, &repos, &config.git.branch)?;
+        repos = graph.sort_repos(&repos)?;
+        Some(graph)
+    } else {
+        None
+    };
+    let mut released_packages: HashMap<String, String> = HashMap::new();
+    logger.info(format!("Repos to update: {:#?}", repos).as_str());
 
     logger.debug("Logging in to AWS...");
-    login(
+    command_log::set_stage(None, "login");
+    if let Err(e) = login(
         &config.git.branch,
         &config.aws.role_script_path,
         &config.aws.role,
         &config.aws.sso_start_url,
-    )?;
+    ) {
+        if command_log::is_cancelled() {
+            report.cancelled = true;
+            let report_path = report.write(&config.report_dir)?;
+            logger
+                .warn(format!("Run {} report: {}", report.run_id, report_path.display()).as_str());
+        }
+        return Err(e);
+    }
     logger.debug("Logged in to AWS");
 
+    if config.preflight {
+        command_log::set_stage(None, "preflight");
+        let failures = preflight(&config, &repos);
+        if !failures.is_empty() {
+            let mut summary = String::new();
+            for (repo, problems) in failures.iter() {
+                summary.push_str(&format!("\n{}:\n  - {}", repo, problems.join("\n  - ")));
+                let repo_path = Path::new(&config.root).join(repo);
+                let repo_report = report.add_repo(repo, &repo_path.to_string_lossy());
+                repo_report.status = RepoStatus::Failed;
+                repo_report.error = Some(problems.join("; "));
+            }
+            logger.error(format!("Pre-flight checks failed:{}", summary).as_str());
+            report.cancelled = command_log::is_cancelled();
+            let report_path = report.write(&config.report_dir)?;
+            logger
+                .warn(format!("Run {} report: {}", report.run_id, report_path.display()).as_str());
+
+            return Err(CustomError::Preflight(format!(
+                "{} repos failed pre-flight checks",
+                failures.len()
+            )));
+        }
+        logger.info("Pre-flight checks passed");
+    }
+
     let mut results_hash: HashMap<&String, String> = HashMap::new();
     let mut errors_hash: HashMap<&String, String> = HashMap::new();
+    let mut back_merge_hash: HashMap<&String, String> = HashMap::new();
+    let mut repo_states: Vec<RepoState> = Vec::new();
+    let run_id = report.run_id.clone();
+    let ticket_extractor = match config.tickets.as_ref().filter(|tickets| tickets.enabled) {
+        Some(tickets) => Some(TicketExtractor::new(tickets)?),
+        None => None,
+    };
+    let worktree_root = get_worktree_root(&config);
 
     for repo in repos.iter() {
+        if command_log::is_cancelled() {
+            logger.warn("Run cancelled. Remaining repos are not processed");
+            break;
+        }
         logger.debug(format!("Getting repo type for repo: {}", repo).as_str());
         let repo_type = match config.repos.get_repo_type(repo) {
             Ok(repo_type) => repo_type,
@@ -62,24 +190,100 @@ fn main() -> CustomResult<()> {
                 continue;
             }
         };
-        let switcher = BranchSwitcher {
-            target_branch: &config.git.branch,
+        let repo_report = report.add_repo(repo, &repo_path);
+        let cloner = RepoCloner {
+            root: &config.root,
+            repo_name: repo,
+            branch: &config.git.branch,
+            release_branch: &config.git.release_branch,
+            config: &config.clone,
         };
+        if !cloner.is_present() {
+            let error = match (clone_errors.get(repo), &config.clone) {
+                (Some(clone_error), _) => format!("Failed to clone repo: {}", clone_error),
+                (None, None) => format!(
+                    "Repo not found at path: {}. Set clone.remote_url_template to clone it",
+                    repo_path
+                ),
+                (None, Some(_)) => format!("Repo not found at path: {}", repo_path),
+            };
+            errors_hash.insert(repo, error);
+            continue;
+        }
 
-        logger.debug(format!("Checking out to target branch for repo: {}", repo_path).as_str());
-        match switcher.checkout_target_branch(&repo_path) {
-            Ok(_) => {}
-            Err(e) => {
-                errors_hash.insert(repo, e.to_string());
-                continue;
+        command_log::set_stage(Some(repo), "checkout");
+        let repo_settings = config.repo_settings.get(repo);
+        let hooks = HookRunner {
+            repo_name: repo,
+            branch: &config.git.branch,
+            release_branch: &config.git.release_branch,
+            global: &config.hooks,
+            repo: repo_settings.map(|settings| &settings.hooks),
+        };
+        if let Err(e) = hooks.run(Hook::PreCheckout, HookContext::new(&repo_path)) {
+            errors_hash.insert(repo, e.to_string());
+            continue;
+        }
+
+        // Dropped at the end of the iteration, whichever way the repo is left
+        let mut worktree = None;
+        let repo_path = if let Some(worktree_root) = &worktree_root {
+            let manager = WorktreeManager {
+                repo_path: &repo_path,
+                branch: &config.git.branch,
+            };
+            let worktree_path = worktree_root.join(format!("{}-{}", repo, run_id));
+            match manager.create_guard(&worktree_path) {
+                Ok(guard) => worktree.insert(guard).path.clone(),
+                Err(e) => {
+                    errors_hash.insert(repo, e.to_string());
+                    continue;
+                }
             }
+        } else {
+            let switcher = BranchSwitcher {
+                target_branch: &config.git.branch,
+                policy: config.checkout_policy,
+            };
+
+            logger.debug(format!("Checking out to target branch for repo: {}", repo_path).as_str());
+            match switcher.checkout_target_branch(&repo_path) {
+                Ok(state) => repo_states.push(state),
+                Err(e) => {
+                    errors_hash.insert(repo, e.to_string());
+                    continue;
+                }
+            };
+            logger.debug(format!("Checked out to target branch for repo: {}", repo_path).as_str());
+            repo_path
         };
-        logger.debug(format!("Checked out to target branch for repo: {}", repo_path).as_str());
+        if let Err(e) = hooks.run(Hook::PostCheckout, HookContext::new(&repo_path)) {
+            errors_hash.insert(repo, e.to_string());
+            continue;
+        }
 
-        let history_provider = HistoryProvider { path: &repo_path };
+        command_log::set_stage(Some(repo), "history");
+        let history_provider = HistoryProvider {
+            path: &repo_path,
+            branch: &config.git.branch,
+            release_branch: &config.git.release_branch,
+            release_branch_base: config.git.release_branch_base.as_ref(),
+            first_parent: config.history.first_parent,
+            mode: config.history.mode,
+            include: repo_settings
+                .map(|settings| settings.include.as_slice())
+                .unwrap_or_default(),
+            exclude: repo_settings
+                .map(|settings| settings.exclude.as_slice())
+                .unwrap_or_default(),
+        };
         logger.debug(format!("Collecting repo history: {}", repo_path).as_str());
         let history = match history_provider.provide() {
-            Ok(history) => history,
+            Ok(history) => {
+                repo_report.history_range = Some(history.range);
+                repo_report.comparison = history.comparison;
+                history.commits
+            }
             Err(e) => {
                 errors_hash.insert(repo, e.to_string());
                 continue;
@@ -88,34 +292,245 @@ fn main() -> CustomResult<()> {
         logger.debug(
             format!(
                 "Collected repo history: {}. Results: {:?}",
-                repo_path, history
+                repo_path,
+                get_subjects_string(&history)
             )
             .as_str(),
         );
 
-        if config.process_only_updated_repo && history.is_empty() {
-            logger.warn(format!("Skipping repo: {}. No history found\n\n", repo_path).as_str());
-            continue;
+        command_log::set_stage(Some(repo), "dependencies");
+        let dependency_updater = DependencyUpdater {
+            path: &repo_path,
+            released: &released_packages,
+        };
+        let dependency_updates = match dependency_updater.get_updates() {
+            Ok(updates) => updates,
+            Err(e) => {
+                errors_hash.insert(repo, e.to_string());
+                continue;
+            }
+        };
+        if !dependency_updates.is_empty() {
+            logger.warn(
+                format!(
+                    "Dependency updates for repo: {}. {}",
+                    repo_path,
+                    get_commit_message(&dependency_updates)
+                )
+                .as_str(),
+            );
+            if config.version_update_required {
+                let publish_timeout = config
+                    .dependencies
+                    .as_ref()
+                    .map(|dependencies| dependencies.publish_timeout)
+                    .unwrap_or_default();
+                let result = if publish_timeout > 0 {
+                    dependency_updater.wait_for_publication(
+                        &dependency_updates,
+                        Duration::from_secs(publish_timeout),
+                    )
+                } else {
+                    Ok(())
+                };
+                if let Err(e) = result.and_then(|_| dependency_updater.apply(&dependency_updates)) {
+                    errors_hash.insert(repo, e.to_string());
+                    continue;
+                }
+            }
         }
 
-        if config.repo_rebuild_required {
+        command_log::set_stage(Some(repo), "release_rules");
+        let skip_reason = if !config.process_only_updated_repo {
+            None
+        } else if history.is_empty() {
+            Some("No history found".to_string())
+        } else if let Some(rules) = config.release_rules.as_ref().filter(|rules| rules.enabled) {
+            let checker = ReleaseRulesChecker {
+                path: &repo_path,
+                config: rules,
+                include: history_provider.include,
+                exclude: history_provider.exclude,
+            };
+            match checker.get_skip_reason(&history) {
+                Ok(skip_reason) => skip_reason,
+                Err(e) => {
+                    errors_hash.insert(repo, e.to_string());
+                    continue;
+                }
+            }
+        } else {
+            None
+        };
+        if let Some(skip_reason) = skip_reason {
+            if dependency_updates.is_empty() || !release_consumers {
+                if !dependency_updates.is_empty() && config.version_update_required {
+                    if let Err(e) = dependency_updater.commit_updates(
+                        &dependency_updates,
+                        &config.git.branch,
+                        config.disable_checks,
+                    ) {
+                        errors_hash.insert(repo, e.to_string());
+                    }
+                }
+                logger.warn(format!("Skipping repo: {}. {}\n\n", repo_path, skip_reason).as_str());
+                repo_report.status = RepoStatus::Skipped;
+                repo_report.skip_reason = Some(skip_reason);
+                continue;
+            }
+            logger.warn(
+                format!(
+                    "Releasing repo: {}. {}, but dependencies were updated",
+                    repo_path, skip_reason
+                )
+                .as_str(),
+            );
+        }
+        let mut notes: Vec<String> = Vec::new();
+        if !dependency_updates.is_empty() {
+            notes.push(get_commit_message(&dependency_updates));
+        }
+        let subjects: Vec<&str> = history
+            .iter()
+            .map(|commit| commit.subject.as_str())
+            .chain(notes.iter().map(String::as_str))
+            .collect();
+        let changes = group_by_type(&subjects);
+        let tickets = match &ticket_extractor {
+            Some(extractor) => match history_provider.get_merge_subjects(&history) {
+                Ok(merge_subjects) => Some(extractor.group(&history, &merge_subjects, &notes)),
+                Err(e) => {
+                    errors_hash.insert(repo, e.to_string());
+                    continue;
+                }
+            },
+            None => None,
+        };
+        let history_string = render_changes(&changes, tickets.as_deref());
+        repo_report.changes = Some(changes);
+        repo_report.tickets = tickets;
+
+        command_log::set_stage(Some(repo), "rebuild");
+        let clean_targets = get_clean_targets(repo_type, repo_settings);
+        let build_cache = config
+            .build_cache
+            .as_ref()
+            .filter(|cache| cache.enabled && config.repo_rebuild_required)
+            .map(|cache| BuildCache {
+                repo_name: repo,
+                repo: &repo_path,
+                repo_type,
+                clean_targets: &clean_targets,
+                python: &config.python,
+                config: cache,
+            });
+        let fingerprint = match build_cache
+            .as_ref()
+            .map(|cache| cache.get_fingerprint())
+            .transpose()
+        {
+            Ok(fingerprint) => fingerprint.flatten(),
+            Err(e) => {
+                errors_hash.insert(repo, e.to_string());
+                continue;
+            }
+        };
+        let is_build_fresh = match (&build_cache, &fingerprint) {
+            (Some(cache), Some(fingerprint)) if !cli_args.force_rebuild => {
+                match cache.is_fresh(fingerprint) {
+                    Ok(is_fresh) => is_fresh,
+                    Err(e) => {
+                        errors_hash.insert(repo, e.to_string());
+                        continue;
+                    }
+                }
+            }
+            _ => false,
+        };
+        if is_build_fresh {
+            logger.warn(
+                format!(
+                    "Build cache is up to date. Skipping repo rebuild: {}",
+                    repo_path
+                )
+                .as_str(),
+            );
+            repo_report.rebuild_cached = true;
+        } else if config.repo_rebuild_required {
             logger.debug(format!("Rebuilding repo: {}", repo_path).as_str());
+            if let Err(e) = hooks.run(Hook::PreRebuild, HookContext::new(&repo_path)) {
+                errors_hash.insert(repo, e.to_string());
+                continue;
+            }
             let rebuilder = RepoRebuilder {
+                repo_name: repo,
                 repo: &repo_path,
                 repo_type,
+                clean_targets: &clean_targets,
+                python: &config.python,
             };
             match rebuilder.rebuild_repo() {
-                Ok(_) => {}
+                Ok(clean_result) => repo_report.cleaned = Some(clean_result),
                 Err(e) => {
                     errors_hash.insert(repo, e.to_string());
                     continue;
                 }
             };
+            if let Err(e) = hooks.run(Hook::PostRebuild, HookContext::new(&repo_path)) {
+                errors_hash.insert(repo, e.to_string());
+                continue;
+            }
+            if let (Some(cache), Some(fingerprint)) = (&build_cache, &fingerprint) {
+                // Without the entry the next run just rebuilds again
+                if let Err(e) = cache.store(fingerprint) {
+                    logger.warn(
+                        format!("Failed to store build cache: {}. {}", repo_path, e).as_str(),
+                    );
+                }
+            }
             logger.debug(format!("Rebuilt repo: {}", repo_path).as_str());
         } else {
             logger.warn(format!("Dry run mode. Skipping repo rebuild: {}", repo_path).as_str());
         }
 
+        let gates = config.gates.as_ref().filter(|gates| gates.enabled);
+        let build_output = get_build_output(repo, &repo_path, repo_type, &config.python);
+        // Without a rebuild the gates use what is installed, if anything is
+        if gates.is_some() && !config.repo_rebuild_required && !build_output.exists() {
+            let reason = format!(
+                "Repo was not rebuilt and {} is missing",
+                build_output.display()
+            );
+            logger.warn(
+                format!("Skipping quality gates for repo: {}. {}", repo_path, reason).as_str(),
+            );
+            repo_report.gates_skipped = Some(reason);
+        } else if let Some(gates) = gates {
+            command_log::set_stage(Some(repo), "gates");
+            let commands = get_gate_commands(gates, repo_type, repo_settings);
+            let runner = GateRunner {
+                repo_name: repo,
+                repo: &repo_path,
+                repo_type,
+                commands: &commands,
+                python: &config.python,
+            };
+            let results = match runner.run() {
+                Ok(results) => results,
+                Err(e) => {
+                    errors_hash.insert(repo, e.to_string());
+                    continue;
+                }
+            };
+            let failed = get_failed_gates(&results).join(", ");
+            repo_report.gates = Some(results);
+            if !failed.is_empty() {
+                errors_hash.insert(repo, format!("Quality gates failed: {}", failed));
+                continue;
+            }
+        }
+
+        command_log::set_stage(Some(repo), "version");
         let selecter = VersionSelecter {
             expected_version: &config.git.version,
             repo: &repo_path,
@@ -136,14 +551,38 @@ fn main() -> CustomResult<()> {
             )
             .as_str(),
         );
+        repo_report.current_version = Some(current_version.clone());
+        repo_report.next_version = Some(next_version.clone());
+
+        logger.warn(format!("\n\n{}\nrelease/{}\n{}", repo, next_version, history_string).as_str());
+
+        if let Some(base) = &config.git.release_branch_base {
+            command_log::set_stage(Some(repo), "release_branch");
+            let creator = ReleaseBranchCreator {
+                path: &repo_path,
+                release_branch: &config.git.release_branch,
+                base,
+                disable_checks: config.disable_checks,
+            };
+            match creator.ensure_exists(!config.version_update_required) {
+                Ok(created_from) => repo_report.release_branch_created_from = created_from,
+                Err(e) => {
+                    errors_hash.insert(repo, e.to_string());
+                    continue;
+                }
+            }
+        }
 
-        result_string.push_str(&format!(
-            "{}\nrelease/{}\n{}\n",
-            repo, next_version, history
-        ));
-        logger.warn(format!("\n\n{}\nrelease/{}\n{}", repo, next_version, history).as_str());
+        let package_name = dependency_graph
+            .as_ref()
+            .and_then(|graph| graph.get_package_name(repo))
+            .cloned();
 
         if !config.version_update_required {
+            repo_report.status = RepoStatus::DryRun;
+            if let Some(package_name) = package_name {
+                released_packages.insert(package_name, next_version);
+            }
             logger.debug(
                 format!(
                     "Dry run mode. Skipping version update in repo: {}",
@@ -154,9 +593,28 @@ fn main() -> CustomResult<()> {
             continue;
         }
 
+        command_log::set_stage(Some(repo), "patch");
+        let package_files = config
+            .verify
+            .as_ref()
+            .map(|verify| get_required_files(verify, repo_type, repo_settings))
+            .unwrap_or_default();
+        let verifier = config
+            .verify
+            .as_ref()
+            .filter(|verify| verify.enabled)
+            .map(|verify| PackageVerifier {
+                repo_name: repo,
+                repo: &repo_path,
+                repo_type,
+                required_files: &package_files,
+                python: &config.python,
+                config: verify,
+            });
+
         logger.debug(format!("Updating version in repo: {}", repo_path).as_str());
         let patcher = Patcher {
-            next_version,
+            next_version: next_version.clone(),
             current_version,
             path: &repo_path,
             repo_type,
@@ -166,30 +624,141 @@ fn main() -> CustomResult<()> {
             role: &config.aws.role,
             sso_script_path: &config.aws.role_script_path,
             disable_checks: config.disable_checks,
+            history: &history,
+            notes: &notes,
+            release_notes: &history_string,
+            changelog: &config.changelog,
+            hooks: &hooks,
+            verifier: verifier.as_ref(),
         };
 
-        let result = match patcher.update_version_in_repo() {
-            Ok(result) => result,
-            Err(e) => {
-                errors_hash.insert(repo, e.to_string());
-                continue;
-            }
-        };
-        logger.warn(format!("{}\n\n", result).as_str());
+        let mut result = PatchResult::default();
+        let patched = patcher.update_version_in_repo(&mut result);
+        // Whatever got published is recorded, so rollback can undo it
+        repo_report.tag = result.tag;
+        repo_report.package = result.package;
+        repo_report.release_commit = result.commit;
+        repo_report.pr_id = result.pr_id;
+        repo_report.pr_link = result.pr_link.clone();
+        if let Err(e) = patched {
+            errors_hash.insert(repo, e.to_string());
+            continue;
+        }
+        let pr_link = result.pr_link.unwrap_or_default();
+        logger.warn(format!("{}\n\n", pr_link).as_str());
+        repo_report.status = RepoStatus::Released;
+        // Kept next to the released status
+        if let Some(hook_error) = result.hook_error {
+            errors_hash.insert(repo, hook_error);
+        }
 
-        results_hash.insert(repo, result);
+        if let Some(package_name) = package_name {
+            released_packages.insert(package_name, next_version);
+        }
+
+        results_hash.insert(repo, pr_link);
         logger.debug(format!("Updated version in repo: {}", repo_path).as_str());
+
+        if let Some(back_merge) = config.back_merge.as_ref().filter(|config| config.enabled) {
+            command_log::set_stage(Some(repo), "back_merge");
+            let back_merger = BackMerger {
+                path: &repo_path,
+                repo_name: repo,
+                branch: &config.git.branch,
+                release_branch: &config.git.release_branch,
+                role: &config.aws.role,
+                sso_script_path: &config.aws.role_script_path,
+                disable_checks: config.disable_checks,
+                mode: back_merge.mode,
+            };
+            match back_merger.back_merge() {
+                Ok(result) => {
+                    back_merge_hash.insert(repo, result.to_string());
+                }
+                // The release itself is out, only the back-merge is left to do by hand
+                Err(e) => {
+                    logger.error(format!("Failed to back-merge repo: {}. {}", repo, e).as_str());
+                    back_merge_hash.insert(repo, format!("Failed: {}", e));
+                }
+            };
+        }
+    }
+
+    // Repos are put back even if the run was cancelled
+    report.cancelled = command_log::is_cancelled();
+    command_log::reset_cancel();
+    command_log::set_stage(None, "cleanup");
+    let switcher = BranchSwitcher {
+        target_branch: &config.git.branch,
+        policy: config.checkout_policy,
+    };
+    for state in repo_states.iter() {
+        if let Err(e) = switcher.restore(state) {
+            logger.error(
+                format!(
+                    "Failed to restore repo: {}. Was on {}, stash: {:?}. {}",
+                    state.path, state.original_ref, state.stash, e
+                )
+                .as_str(),
+            );
+        }
     }
 
+    for (repo, error) in errors_hash.iter() {
+        if let Some(repo_report) = report.get_repo_mut(repo) {
+            // A released repo stays released, the error is kept next to it
+            if repo_report.status != RepoStatus::Released {
+                repo_report.status = RepoStatus::Failed;
+            }
+            repo_report.error = Some(error.clone());
+        }
+    }
+    for (repo, back_merge) in back_merge_hash.iter() {
+        if let Some(repo_report) = report.get_repo_mut(repo) {
+            repo_report.back_merge = Some(back_merge.clone());
+        }
+    }
+    for repo_report in report.repos.iter_mut() {
+        repo_report.logs = command_log::get_files(&repo_report.name);
+    }
+    // The report is needed for rollback, it is written before anything else can fail
+    let report_path = report.write(&config.report_dir)?;
+
     logger.warn(
         format!(
             "Repos history logs:\n{}\nRepos PRs: {:#?}",
-            result_string, results_hash
+            render_release_notes(&report, ReleaseNotesFormat::Plain)?,
+            results_hash
         )
         .as_str(),
     );
+    if !back_merge_hash.is_empty() {
+        logger.warn(format!("Back-merges: {:#?}", back_merge_hash).as_str());
+    }
     logger.warn(format!("Errors: {:#?}", errors_hash).as_str());
 
+    logger.warn(format!("Run {} report: {}", report.run_id, report_path.display()).as_str());
+    if let Some(release_notes) = &config.release_notes {
+        let writer = ReleaseNotesWriter {
+            report: &report,
+            config: release_notes,
+        };
+        match writer.write() {
+            Ok(paths) => {
+                for path in paths {
+                    logger.warn(format!("Release notes: {}", path.display()).as_str());
+                }
+            }
+            Err(e) => logger.error(format!("Failed to write release notes. {}", e).as_str()),
+        }
+    }
+    if report.cancelled {
+        return Err(CustomError::Cancelled(format!(
+            "Run {} was cancelled",
+            report.run_id
+        )));
+    }
+
     logger.info("Version updater finished!");
 
     Ok(())
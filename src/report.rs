@@ -0,0 +1,140 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::custom_error::{CustomError, CustomResult};
+use crate::workers::cleaner::CleanResult;
+use crate::workers::gates::GateResult;
+use crate::workers::history::BranchComparison;
+use crate::workers::release_notes::CommitTypeGroup;
+use crate::workers::tickets::TicketGroup;
+use crate::workers::verifier::PackageInfo;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RepoStatus {
+    Pending,
+    Skipped,
+    DryRun,
+    Released,
+    Failed,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RepoReport {
+    pub name: String,
+    pub path: String,
+    pub status: RepoStatus,
+    pub current_version: Option<String>,
+    pub next_version: Option<String>,
+    pub history_range: Option<String>,
+    pub comparison: Option<BranchComparison>,
+    pub changes: Option<Vec<CommitTypeGroup>>,
+    pub tickets: Option<Vec<TicketGroup>>,
+    pub cleaned: Option<CleanResult>,
+    // The rebuild was skipped, the build cache was up to date
+    #[serde(default)]
+    pub rebuild_cached: bool,
+    pub gates: Option<Vec<GateResult>>,
+    // Why the quality gates could not run
+    pub gates_skipped: Option<String>,
+    pub package: Option<PackageInfo>,
+    pub tag: Option<String>,
+    pub release_branch_created_from: Option<String>,
+    pub release_commit: Option<String>,
+    pub pr_id: Option<String>,
+    pub pr_link: Option<String>,
+    pub back_merge: Option<String>,
+    pub skip_reason: Option<String>,
+    pub error: Option<String>,
+    // Command output of the repo, one log file per stage
+    #[serde(default)]
+    pub logs: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunReport {
+    pub run_id: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    // Stopped by Ctrl-C or the run timeout, repos after the cancelled one are missing
+    #[serde(default)]
+    pub cancelled: bool,
+    pub branch: String,
+    pub release_branch: String,
+    pub repos: Vec<RepoReport>,
+}
+
+impl RunReport {
+    pub fn new(branch: &str, release_branch: &str) -> Self {
+        let started_at = Utc::now();
+        Self {
+            run_id: started_at.format("%Y%m%d-%H%M%S").to_string(),
+            started_at,
+            finished_at: None,
+            cancelled: false,
+            branch: branch.to_string(),
+            release_branch: release_branch.to_string(),
+            repos: Vec::new(),
+        }
+    }
+
+    pub fn add_repo(&mut self, name: &str, path: &str) -> &mut RepoReport {
+        self.repos.push(RepoReport {
+            name: name.to_string(),
+            path: path.to_string(),
+            status: RepoStatus::Pending,
+            current_version: None,
+            next_version: None,
+            history_range: None,
+            comparison: None,
+            changes: None,
+            tickets: None,
+            cleaned: None,
+            rebuild_cached: false,
+            gates: None,
+            gates_skipped: None,
+            package: None,
+            tag: None,
+            release_branch_created_from: None,
+            release_commit: None,
+            pr_id: None,
+            pr_link: None,
+            back_merge: None,
+            skip_reason: None,
+            error: None,
+            logs: Vec::new(),
+        });
+        self.repos.last_mut().unwrap()
+    }
+
+    pub fn get_repo_mut(&mut self, name: &str) -> Option<&mut RepoReport> {
+        self.repos.iter_mut().find(|repo| repo.name == name)
+    }
+
+    pub fn write(&mut self, dir: &str) -> CustomResult<PathBuf> {
+        self.finished_at = Some(Utc::now());
+        fs::create_dir_all(dir).map_err(|err| CustomError::FileSystem(err.to_string()))?;
+
+        let path = get_report_path(dir, &self.run_id);
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|err| CustomError::FileSystem(err.to_string()))?;
+        fs::write(&path, content).map_err(|err| CustomError::FileSystem(err.to_string()))?;
+
+        Ok(path)
+    }
+
+    pub fn read(dir: &str, run_id: &str) -> CustomResult<Self> {
+        let path = get_report_path(dir, run_id);
+        let content = fs::read_to_string(&path).map_err(|err| {
+            CustomError::FileSystem(format!("Could not read `{}`: {}", path.display(), err))
+        })?;
+
+        serde_json::from_str(&content).map_err(|err| CustomError::FileSystem(err.to_string()))
+    }
+}
+
+fn get_report_path(dir: &str, run_id: &str) -> PathBuf {
+    Path::new(dir).join(format!("{}.json", run_id))
+}
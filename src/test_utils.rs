@@ -0,0 +1,82 @@
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+// Empty directory under the system temp dir for a test. It is removed on drop,
+// so a failing test does not leave it behind.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("version_updater_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        Self { path }
+    }
+
+    pub fn to_path_string(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+// Runs git in `dir` and returns its trimmed stdout. Panics on failure, so a broken
+// fixture shows up as the failing step instead of a wrong assertion later on.
+pub fn git(dir: &Path, args: &[&str]) -> String {
+    let output = std::process::Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+// Commits `content` to `file` and returns the new commit hash.
+pub fn commit_file(dir: &Path, file: &str, content: &str, message: &str) -> String {
+    fs::write(dir.join(file), content).unwrap();
+    git(dir, &["add", file]);
+    git(dir, &["commit", "-q", "-m", message]);
+
+    git(dir, &["rev-parse", "HEAD"])
+}
+
+// Creates a bare `origin` and a clone of it in `work` with one commit on `main`
+// pushed, so tests can work against `origin/main` like a real checkout.
+pub fn init_git_repo(root: &Path) -> PathBuf {
+    let origin = root.join("origin.git");
+    let work = root.join("work");
+    fs::create_dir_all(&origin).unwrap();
+    git(&origin, &["init", "-q", "--bare", "-b", "main"]);
+    git(root, &["clone", "-q", "origin.git", "work"]);
+    git(&work, &["config", "user.email", "test@example.com"]);
+    git(&work, &["config", "user.name", "Test"]);
+    git(&work, &["checkout", "-q", "-b", "main"]);
+    commit_file(&work, "README.md", "init\n", "Initial commit");
+    git(&work, &["push", "-q", "origin", "main"]);
+
+    work
+}
@@ -0,0 +1,324 @@
+use std::fmt::{Display, Formatter};
+use std::process::{Command, Output};
+
+use crate::{
+    command_log::LoggedCommand,
+    config::BackMergeMode,
+    custom_error::{CustomError, CustomResult},
+    logger::LoggerTrait,
+    workers::loginer::get_switch_role_command,
+    workers::patcher::{get_pr_link, CreatePullRequestOutput},
+};
+
+#[derive(Debug)]
+pub enum BackMergeResult {
+    UpToDate,
+    Merged,
+    PullRequest(String),
+    Conflicts(Vec<String>),
+}
+
+impl Display for BackMergeResult {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Self::UpToDate => write!(f, "up to date"),
+            Self::Merged => write!(f, "merged"),
+            Self::PullRequest(link) => write!(f, "PR: {}", link),
+            Self::Conflicts(files) => write!(f, "conflicts: {}", files.join(", ")),
+        }
+    }
+}
+
+pub struct BackMerger<'repo> {
+    pub path: &'repo String,
+    pub repo_name: &'repo String,
+    pub branch: &'repo String,
+    pub release_branch: &'repo String,
+    pub role: &'repo String,
+    pub sso_script_path: &'repo String,
+    pub disable_checks: bool,
+    pub mode: BackMergeMode,
+}
+
+impl LoggerTrait for BackMerger<'_> {}
+impl BackMerger<'_> {
+    pub fn back_merge(&self) -> CustomResult<BackMergeResult> {
+        let logger = self.get_logger();
+        logger.info(
+            format!(
+                "Back-merging {} into {} for repo: {}",
+                self.release_branch, self.branch, self.path
+            )
+            .as_str(),
+        );
+
+        self.run_git(&["fetch", "origin"], "Failed to fetch repo")?;
+
+        if self.is_merged()? {
+            logger.info(format!("Nothing to back-merge for repo: {}", self.path).as_str());
+            return Ok(BackMergeResult::UpToDate);
+        }
+
+        let conflicts = self.get_conflicts()?;
+        if !conflicts.is_empty() {
+            logger.warn(
+                format!(
+                    "Back-merge conflicts for repo: {}. Files: {:?}",
+                    self.path, conflicts
+                )
+                .as_str(),
+            );
+            return Ok(BackMergeResult::Conflicts(conflicts));
+        }
+
+        let result = match self.mode {
+            BackMergeMode::Merge => {
+                self.merge()?;
+                self.push()?;
+                BackMergeResult::Merged
+            }
+            BackMergeMode::PullRequest => BackMergeResult::PullRequest(self.create_pr()?),
+        };
+        logger.info(format!("Back-merged repo: {}. {}", self.path, result).as_str());
+
+        Ok(result)
+    }
+
+    fn is_merged(&self) -> CustomResult<bool> {
+        let output = Command::new("git")
+            .arg("merge-base")
+            .arg("--is-ancestor")
+            .arg(format!("origin/{}", self.release_branch))
+            .arg(format!("origin/{}", self.branch))
+            .current_dir(self.path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+
+        match output.status.code() {
+            Some(0) => Ok(true),
+            Some(1) => Ok(false),
+            _ => {
+                self.log_failure("Failed to compare branches", &output);
+                Err(CustomError::CommandExecution(
+                    "Failed to compare branches".to_string(),
+                ))
+            }
+        }
+    }
+
+    // Merges the branches in memory only, so the working tree never ends up conflicted.
+    fn get_conflicts(&self) -> CustomResult<Vec<String>> {
+        let output = Command::new("git")
+            .arg("merge-tree")
+            .arg("--write-tree")
+            .arg("--name-only")
+            .arg("--no-messages")
+            .arg(format!("origin/{}", self.branch))
+            .arg(format!("origin/{}", self.release_branch))
+            .current_dir(self.path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+
+        match output.status.code() {
+            Some(0) => Ok(Vec::new()),
+            Some(1) => Ok(parse_conflicts(&String::from_utf8_lossy(&output.stdout))),
+            _ => {
+                self.log_failure("Failed to check back-merge conflicts", &output);
+                Err(CustomError::CommandExecution(
+                    "Failed to check back-merge conflicts".to_string(),
+                ))
+            }
+        }
+    }
+
+    fn merge(&self) -> CustomResult<()> {
+        let message = format!(
+            "Merge branch '{}' into {}",
+            self.release_branch, self.branch
+        );
+        let release = format!("origin/{}", self.release_branch);
+        let mut args = vec!["merge", "--no-ff", "-m", &message, &release];
+        if self.disable_checks {
+            args.push("--no-verify");
+        }
+
+        if let Err(err) = self.run_git(&args, "Failed to back-merge release branch") {
+            let _ = self.run_git(&["merge", "--abort"], "Failed to abort back-merge");
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    fn push(&self) -> CustomResult<()> {
+        let target = format!("HEAD:{}", self.branch);
+        let mut args = vec!["push", "origin", &target];
+        if self.disable_checks {
+            args.push("--no-verify");
+        }
+        self.run_git(&args, "Failed to push back-merge")?;
+
+        Ok(())
+    }
+
+    fn create_pr(&self) -> CustomResult<String> {
+        let logger = self.get_logger();
+        logger.info(format!("Creating back-merge PR in AWS: {}", self.path).as_str());
+
+        let command_string = format!(
+            r#"
+                {0}
+                aws codecommit create-pull-request --title 'Back-merge {1} into {2}' --targets repositoryName={3},sourceReference={1},destinationReference={2}
+            "#,
+            get_switch_role_command(self.sso_script_path, self.role),
+            self.release_branch,
+            self.branch,
+            self.repo_name
+        );
+        let output = Command::new("zsh")
+            .arg("-c")
+            .arg(&command_string)
+            .current_dir(self.path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            self.log_failure("Failed to create back-merge PR", &output);
+            return Err(CustomError::CommandExecution(
+                "Failed to create back-merge PR".to_string(),
+            ));
+        }
+
+        let commit: CreatePullRequestOutput = serde_json::from_slice(&output.stdout)
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        let pr_link = get_pr_link(self.repo_name, &commit.pull_request.pull_request_id);
+        logger.warn(format!("Created back-merge PR: {}, PR: {}", self.path, pr_link).as_str());
+
+        Ok(pr_link)
+    }
+
+    fn run_git(&self, args: &[&str], error: &str) -> CustomResult<Output> {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(self.path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            self.log_failure(error, &output);
+            return Err(CustomError::CommandExecution(error.to_string()));
+        }
+
+        Ok(output)
+    }
+
+    fn log_failure(&self, error: &str, output: &Output) {
+        let logger = self.get_logger();
+        logger.error(format!("{} for repo: {}", error, self.path).as_str());
+        logger.error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+    }
+}
+
+// `git merge-tree --name-only` prints the tree id, the conflicted files and, after an
+// empty line, the informational messages. Only the file list is returned, once per file.
+fn parse_conflicts(output: &str) -> Vec<String> {
+    let mut files: Vec<String> = Vec::new();
+    for line in output.lines().skip(1).take_while(|line| !line.is_empty()) {
+        if !files.iter().any(|file| file == line) {
+            files.push(line.to_string());
+        }
+    }
+
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{commit_file, git, init_git_repo, TempDir};
+
+    fn get_merger<'a>(path: &'a String, names: &'a [String; 5]) -> BackMerger<'a> {
+        BackMerger {
+            path,
+            repo_name: &names[0],
+            branch: &names[1],
+            release_branch: &names[2],
+            role: &names[3],
+            sso_script_path: &names[4],
+            disable_checks: true,
+            mode: BackMergeMode::Merge,
+        }
+    }
+
+    fn get_names() -> [String; 5] {
+        [
+            String::from("repo"),
+            String::from("main"),
+            String::from("release"),
+            String::new(),
+            String::new(),
+        ]
+    }
+
+    #[test]
+    fn test_parse_conflicts_reads_clean_output() {
+        assert!(parse_conflicts("4b825dc642cb6eb9a060e54bf8d69288fbee4904\n").is_empty());
+    }
+
+    #[test]
+    fn test_parse_conflicts_reads_file_list() {
+        let output = "4b825dc642cb6eb9a060e54bf8d69288fbee4904\nsrc/a.rs\nsrc/a.rs\npackage.json\n";
+
+        assert_eq!(parse_conflicts(output), vec!["src/a.rs", "package.json"]);
+    }
+
+    #[test]
+    fn test_parse_conflicts_ignores_messages_after_separator() {
+        let output = "4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+            package.json\n\
+            \n\
+            Auto-merging package.json\n\
+            CONFLICT (content): Merge conflict in package.json\n";
+
+        assert_eq!(parse_conflicts(output), vec!["package.json"]);
+    }
+
+    #[test]
+    fn test_back_merge_checks_against_origin() {
+        let dir = TempDir::new("back_merge_checks");
+        let work = init_git_repo(&dir);
+        let path = work.to_string_lossy().to_string();
+        let names = get_names();
+        let merger = get_merger(&path, &names);
+
+        git(&work, &["push", "-q", "origin", "main:release"]);
+        git(&work, &["fetch", "-q", "origin"]);
+        assert!(merger.is_merged().unwrap());
+
+        git(
+            &work,
+            &["checkout", "-q", "-b", "release", "origin/release"],
+        );
+        commit_file(
+            &work,
+            "package.json",
+            "{\"version\": \"1.0.1\"}\n",
+            "@1.0.1 release",
+        );
+        git(&work, &["push", "-q", "origin", "release"]);
+        git(&work, &["checkout", "-q", "main"]);
+        commit_file(&work, "src.txt", "feature\n", "feat: feature");
+        git(&work, &["push", "-q", "origin", "main"]);
+        git(&work, &["fetch", "-q", "origin"]);
+        assert!(!merger.is_merged().unwrap());
+        assert!(merger.get_conflicts().unwrap().is_empty());
+
+        commit_file(
+            &work,
+            "package.json",
+            "{\"version\": \"2.0.0\"}\n",
+            "chore: bump",
+        );
+        git(&work, &["push", "-q", "origin", "main"]);
+        git(&work, &["fetch", "-q", "origin"]);
+        assert_eq!(merger.get_conflicts().unwrap(), vec!["package.json"]);
+    }
+}
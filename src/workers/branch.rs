@@ -1,25 +1,208 @@
-use std::process::Command;
+use std::process::{Command, Output};
 
 use crate::{
+    command_log::LoggedCommand,
+    config::CheckoutPolicy,
     custom_error::{CustomError, CustomResult},
     logger::LoggerTrait,
 };
 
+// State of the developer's checkout before the switch, used to put it back afterwards.
+#[derive(Debug)]
+pub struct RepoState {
+    pub path: String,
+    pub original_ref: String,
+    pub stash: Option<String>,
+}
+
 pub struct BranchSwitcher<'branch> {
     pub target_branch: &'branch String,
+    pub policy: CheckoutPolicy,
 }
 
 impl<'config> LoggerTrait for BranchSwitcher<'config> {}
 impl<'branch> BranchSwitcher<'branch> {
-    pub fn checkout_target_branch(&self, repo_path: &str) -> CustomResult<()> {
-        self.stash_repo(repo_path)?;
+    pub fn checkout_target_branch(&self, repo_path: &str) -> CustomResult<RepoState> {
+        let logger = self.get_logger();
         self.fetch_repo(repo_path)?;
-        self.switch_branch(repo_path)?;
-        self.hard_reset_branch(repo_path)?;
+
+        let original_ref = self.get_current_ref(repo_path)?;
+        let dirty = self.is_dirty(repo_path)?;
+        let ahead = self.get_commits_ahead(repo_path)?;
+        logger.debug(
+            format!(
+                "Repo state: {}. ref={}, dirty={}, ahead={}",
+                repo_path, original_ref, dirty, ahead
+            )
+            .as_str(),
+        );
+
+        if ahead > 0 {
+            let message = format!(
+                "{} has {} local commits not pushed to origin/{}",
+                self.target_branch, ahead, self.target_branch
+            );
+            if self.policy == CheckoutPolicy::Force {
+                logger.warn(format!("{} in repo: {}. Discarding", message, repo_path).as_str());
+            } else {
+                return Err(CustomError::DirtyRepo(message));
+            }
+        }
+        if dirty && self.policy == CheckoutPolicy::Refuse {
+            return Err(CustomError::DirtyRepo(
+                "Repo has uncommitted changes".to_string(),
+            ));
+        }
+
+        let stash = if dirty {
+            Some(self.stash_repo(repo_path)?)
+        } else {
+            None
+        };
+        let state = RepoState {
+            path: repo_path.to_string(),
+            original_ref,
+            stash,
+        };
+
+        if let Err(err) = self
+            .switch_branch(repo_path)
+            .and_then(|_| self.hard_reset_branch(repo_path))
+        {
+            let _ = self.restore(&state);
+            return Err(err);
+        }
+
+        Ok(state)
+    }
+
+    // Returns the repo to the branch it was on and re-applies the stash. `git checkout`
+    // would carry uncommitted changes over, so leftovers of a failed release are stashed
+    // first and the developer's branch gets back exactly what was stashed.
+    pub fn restore(&self, state: &RepoState) -> CustomResult<()> {
+        let logger = self.get_logger();
+        logger.info(format!("Restoring repo: {} to {}", state.path, state.original_ref).as_str());
+        if self.is_dirty(&state.path)? {
+            let leftovers = self.stash_leftovers(&state.path)?;
+            logger.warn(
+                format!(
+                    "Repo: {} had uncommitted changes left on {}. Stashed them as {}",
+                    state.path, self.target_branch, leftovers
+                )
+                .as_str(),
+            );
+        }
+        self.run_git(
+            &state.path,
+            &["checkout", &state.original_ref],
+            "Failed to restore branch for repo",
+        )?;
+
+        if let Some(stash) = &state.stash {
+            self.run_git(
+                &state.path,
+                &["stash", "apply", "--index", stash],
+                "Failed to apply stash for repo",
+            )?;
+            let output = self.run_git(
+                &state.path,
+                &["stash", "list", "--format=%H"],
+                "Failed to list stashes for repo",
+            )?;
+            let index = String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .position(|hash| hash == stash);
+            if let Some(index) = index {
+                self.run_git(
+                    &state.path,
+                    &["stash", "drop", &format!("stash@{{{}}}", index)],
+                    "Failed to drop stash for repo",
+                )?;
+            }
+        }
+        logger.info(format!("Restored repo: {}", state.path).as_str());
 
         Ok(())
     }
 
+    fn get_current_ref(&self, repo_path: &str) -> CustomResult<String> {
+        let output = Command::new("git")
+            .arg("symbolic-ref")
+            .arg("--short")
+            .arg("-q")
+            .arg("HEAD")
+            .current_dir(repo_path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if output.status.success() {
+            return Ok(String::from_utf8_lossy(&output.stdout).trim().to_string());
+        }
+
+        // Detached HEAD, remember the commit instead
+        let output = self.run_git(
+            repo_path,
+            &["rev-parse", "HEAD"],
+            "Failed to get current commit for repo",
+        )?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    fn is_dirty(&self, repo_path: &str) -> CustomResult<bool> {
+        let output = self.run_git(
+            repo_path,
+            &["status", "--porcelain"],
+            "Failed to get status for repo",
+        )?;
+
+        Ok(!output.stdout.is_empty())
+    }
+
+    fn get_commits_ahead(&self, repo_path: &str) -> CustomResult<usize> {
+        let local = format!("refs/heads/{}", self.target_branch);
+        let exists = Command::new("git")
+            .arg("rev-parse")
+            .arg("--verify")
+            .arg("-q")
+            .arg(&local)
+            .current_dir(repo_path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?
+            .status
+            .success();
+        if !exists {
+            return Ok(0);
+        }
+
+        let range = format!("origin/{0}..{0}", self.target_branch);
+        let output = self.run_git(
+            repo_path,
+            &["rev-list", "--count", &range],
+            "Failed to count local commits for repo",
+        )?;
+
+        String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .parse::<usize>()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))
+    }
+
+    fn run_git(&self, repo_path: &str, args: &[&str], error: &str) -> CustomResult<Output> {
+        let logger = self.get_logger();
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(repo_path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            logger.error(format!("{}: {}", error, repo_path).as_str());
+            logger.error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+
+            return Err(CustomError::CommandExecution(error.to_string()));
+        }
+
+        Ok(output)
+    }
+
     fn hard_reset_branch(&self, repo_path: &str) -> CustomResult<()> {
         let logger = self.get_logger();
         logger.info(format!("Hard resetting branch for repo: {}", repo_path).as_str());
@@ -28,7 +211,7 @@ impl<'branch> BranchSwitcher<'branch> {
             .arg("--hard")
             .arg(format!("origin/{}", self.target_branch))
             .current_dir(repo_path)
-            .output()
+            .logged_output()
             .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
         if !output.status.success() {
             logger.error(format!("Failed to hard reset branch for repo: {}", repo_path).as_str());
@@ -42,13 +225,30 @@ impl<'branch> BranchSwitcher<'branch> {
         Ok(())
     }
 
-    fn stash_repo(&self, repo_path: &str) -> CustomResult<()> {
+    fn stash_repo(&self, repo_path: &str) -> CustomResult<String> {
+        let message = format!("version_updater: before checkout of {}", self.target_branch);
+        self.stash(repo_path, &message)
+    }
+
+    fn stash_leftovers(&self, repo_path: &str) -> CustomResult<String> {
+        let message = format!(
+            "version_updater: leftovers of the release on {}",
+            self.target_branch
+        );
+        self.stash(repo_path, &message)
+    }
+
+    fn stash(&self, repo_path: &str, message: &str) -> CustomResult<String> {
         let logger = self.get_logger();
         logger.info(format!("Stashing repo: {}", repo_path).as_str());
         let output = Command::new("git")
             .arg("stash")
+            .arg("push")
+            .arg("--include-untracked")
+            .arg("-m")
+            .arg(message)
             .current_dir(repo_path)
-            .output()
+            .logged_output()
             .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
         if !output.status.success() {
             logger.error(format!("Failed to stash repo: {}", repo_path).as_str());
@@ -59,7 +259,15 @@ impl<'branch> BranchSwitcher<'branch> {
             ));
         }
 
-        Ok(())
+        let output = self.run_git(
+            repo_path,
+            &["rev-parse", "stash@{0}"],
+            "Failed to get stash for repo",
+        )?;
+        let stash = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        logger.info(format!("Stashed repo: {} as {}", repo_path, stash).as_str());
+
+        Ok(stash)
     }
 
     fn fetch_repo(&self, repo_path: &str) -> CustomResult<()> {
@@ -68,7 +276,7 @@ impl<'branch> BranchSwitcher<'branch> {
         let output = Command::new("git")
             .arg("fetch")
             .current_dir(repo_path)
-            .output()
+            .logged_output()
             .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
         if !output.status.success() {
             logger.error(format!("Failed to fetch repo: {}", repo_path).as_str());
@@ -89,7 +297,7 @@ impl<'branch> BranchSwitcher<'branch> {
             .arg("checkout")
             .arg(self.target_branch)
             .current_dir(repo_path)
-            .output()
+            .logged_output()
             .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
         if !output.status.success() {
             logger.error(format!("Failed to switch branch for repo: {}", repo_path).as_str());
@@ -103,3 +311,147 @@ impl<'branch> BranchSwitcher<'branch> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::test_utils::{commit_file, git, init_git_repo, TempDir};
+
+    const POLICIES: [CheckoutPolicy; 3] = [
+        CheckoutPolicy::Refuse,
+        CheckoutPolicy::StashAndRestore,
+        CheckoutPolicy::Force,
+    ];
+
+    // A checkout on `feature` with a committed change to README.md
+    fn init_feature_repo(dir: &Path) -> String {
+        let work = init_git_repo(dir);
+        git(&work, &["checkout", "-q", "-b", "feature"]);
+        commit_file(&work, "README.md", "feature\n", "feat: feature");
+
+        work.to_string_lossy().to_string()
+    }
+
+    fn get_stashes(path: &str) -> Vec<String> {
+        git(Path::new(path), &["stash", "list", "--format=%s"])
+            .lines()
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    // Uncommitted changes a failed release leaves on the target branch
+    fn leave_release_changes(path: &str) {
+        let path = Path::new(path);
+        fs::write(path.join("README.md"), "release\n").unwrap();
+        fs::write(path.join("CHANGELOG.md"), "## 1.0.1\n").unwrap();
+    }
+
+    #[test]
+    fn test_checkout_of_dirty_repo() {
+        for policy in POLICIES {
+            let dir = TempDir::new(&format!("branch_dirty_{:?}", policy));
+            let path = init_feature_repo(&dir);
+            fs::write(dir.join("work/notes.txt"), "wip\n").unwrap();
+            let target = String::from("main");
+            let switcher = BranchSwitcher {
+                target_branch: &target,
+                policy,
+            };
+
+            let state = switcher.checkout_target_branch(&path);
+            if policy == CheckoutPolicy::Refuse {
+                assert!(matches!(state, Err(CustomError::DirtyRepo(_))));
+                assert_eq!(
+                    git(&dir.join("work"), &["branch", "--show-current"]),
+                    "feature"
+                );
+                continue;
+            }
+            let state = state.unwrap();
+            assert!(state.stash.is_some());
+            assert!(!dir.join("work/notes.txt").exists());
+
+            leave_release_changes(&path);
+            switcher.restore(&state).unwrap();
+
+            assert_eq!(
+                git(&dir.join("work"), &["branch", "--show-current"]),
+                "feature"
+            );
+            assert_eq!(
+                git(&dir.join("work"), &["status", "--porcelain"]),
+                "?? notes.txt"
+            );
+            assert_eq!(
+                fs::read_to_string(dir.join("work/README.md")).unwrap(),
+                "feature\n"
+            );
+            assert_eq!(
+                get_stashes(&path),
+                vec!["On main: version_updater: leftovers of the release on main"]
+            );
+        }
+    }
+
+    #[test]
+    fn test_checkout_of_repo_ahead_of_origin() {
+        for policy in POLICIES {
+            let dir = TempDir::new(&format!("branch_ahead_{:?}", policy));
+            let path = init_feature_repo(&dir);
+            let work = dir.join("work");
+            git(&work, &["checkout", "-q", "main"]);
+            let local = commit_file(&work, "local.txt", "local\n", "chore: local");
+            let remote = git(&work, &["rev-parse", "origin/main"]);
+            git(&work, &["checkout", "-q", "feature"]);
+            let target = String::from("main");
+            let switcher = BranchSwitcher {
+                target_branch: &target,
+                policy,
+            };
+
+            let state = switcher.checkout_target_branch(&path);
+            if policy != CheckoutPolicy::Force {
+                assert!(matches!(state, Err(CustomError::DirtyRepo(_))));
+                assert_eq!(git(&work, &["rev-parse", "main"]), local);
+                continue;
+            }
+            assert_eq!(git(&work, &["rev-parse", "HEAD"]), remote);
+
+            switcher.restore(&state.unwrap()).unwrap();
+            assert_eq!(git(&work, &["branch", "--show-current"]), "feature");
+            assert!(get_stashes(&path).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_restore_after_failed_release() {
+        for policy in POLICIES {
+            let dir = TempDir::new(&format!("branch_failed_{:?}", policy));
+            let path = init_feature_repo(&dir);
+            let work = dir.join("work");
+            let target = String::from("main");
+            let switcher = BranchSwitcher {
+                target_branch: &target,
+                policy,
+            };
+
+            let state = switcher.checkout_target_branch(&path).unwrap();
+            assert!(state.stash.is_none());
+            leave_release_changes(&path);
+            switcher.restore(&state).unwrap();
+
+            assert_eq!(git(&work, &["branch", "--show-current"]), "feature");
+            assert_eq!(git(&work, &["status", "--porcelain"]), "");
+            assert_eq!(get_stashes(&path).len(), 1);
+            git(&work, &["checkout", "-q", "main"]);
+            git(&work, &["stash", "pop", "-q"]);
+            assert_eq!(
+                fs::read_to_string(work.join("README.md")).unwrap(),
+                "release\n"
+            );
+        }
+    }
+}
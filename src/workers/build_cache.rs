@@ -0,0 +1,306 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    command_log::LoggedCommand,
+    config::{BuildCacheConfig, PythonConfig, RepoType},
+    custom_error::{CustomError, CustomResult},
+    logger::LoggerTrait,
+    workers::{
+        python::get_requirements_files,
+        rebuilder::{get_build_output, PackageManager},
+    },
+};
+
+// Everything the result of a rebuild depends on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildFingerprint {
+    // `HEAD^{tree}`, only taken from a clean working tree
+    pub tree: String,
+    // Blob hashes of the dependency files, they may be ignored by git
+    pub lockfiles: BTreeMap<String, String>,
+    // Versions of node and the package manager, or of the python interpreter
+    pub runtime: String,
+    // Settings that change what the rebuild does
+    pub settings: Vec<String>,
+}
+
+// Stored entry of a repo: the fingerprint and the outputs the rebuild left behind
+#[derive(Debug, Serialize, Deserialize)]
+struct BuildCacheEntry {
+    fingerprint: BuildFingerprint,
+    // Globs of the build outputs that existed after the rebuild
+    outputs: Vec<String>,
+}
+
+// Remembers the fingerprint of the last successful rebuild of each repo,
+// so an unchanged repo is not reinstalled and rebuilt again
+pub struct BuildCache<'repo> {
+    pub repo_name: &'repo String,
+    pub repo: &'repo String,
+    pub repo_type: RepoType,
+    pub clean_targets: &'repo [String],
+    pub python: &'repo PythonConfig,
+    pub config: &'repo BuildCacheConfig,
+}
+
+impl LoggerTrait for BuildCache<'_> {}
+impl BuildCache<'_> {
+    // No fingerprint for a repo with uncommitted changes, it is always rebuilt
+    pub fn get_fingerprint(&self) -> CustomResult<Option<BuildFingerprint>> {
+        let logger = self.get_logger();
+        if !self.run_git(&["status", "--porcelain"])?.is_empty() {
+            logger.debug(format!("Repo has local changes, no build cache: {}", self.repo).as_str());
+            return Ok(None);
+        }
+
+        let lockfiles = self.get_lockfiles()?;
+        let mut hashes = BTreeMap::new();
+        if !lockfiles.is_empty() {
+            let mut args = vec!["hash-object", "--no-filters", "--"];
+            args.extend(lockfiles.iter().map(String::as_str));
+            let output = self.run_git(&args)?;
+            hashes = lockfiles
+                .into_iter()
+                .zip(output.lines().map(String::from))
+                .collect();
+        }
+
+        Ok(Some(BuildFingerprint {
+            tree: self.run_git(&["rev-parse", "HEAD^{tree}"])?,
+            lockfiles: hashes,
+            runtime: self.get_runtime()?,
+            settings: self.get_settings(),
+        }))
+    }
+
+    // The repo is built when the fingerprint matches and every build output is still there
+    pub fn is_fresh(&self, fingerprint: &BuildFingerprint) -> CustomResult<bool> {
+        let content = match fs::read_to_string(self.get_cache_path()) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(CustomError::FileSystem(err.to_string())),
+        };
+
+        // An unreadable entry only costs a rebuild
+        let entry = match serde_json::from_str::<BuildCacheEntry>(&content) {
+            Ok(entry) => entry,
+            Err(_) => return Ok(false),
+        };
+
+        Ok(entry.fingerprint == *fingerprint
+            && entry.outputs.iter().all(|output| has_match(output)))
+    }
+
+    // Called after the rebuild, the outputs it created are remembered with the fingerprint
+    pub fn store(&self, fingerprint: &BuildFingerprint) -> CustomResult<()> {
+        fs::create_dir_all(&self.config.dir)
+            .map_err(|err| CustomError::FileSystem(err.to_string()))?;
+        let entry = BuildCacheEntry {
+            fingerprint: fingerprint.clone(),
+            outputs: self.get_outputs(),
+        };
+        let content = serde_json::to_string_pretty(&entry)
+            .map_err(|err| CustomError::FileSystem(err.to_string()))?;
+        fs::write(self.get_cache_path(), content)
+            .map_err(|err| CustomError::FileSystem(err.to_string()))
+    }
+
+    fn get_cache_path(&self) -> PathBuf {
+        Path::new(&self.config.dir).join(format!("{}.json", self.repo_name))
+    }
+
+    fn get_lockfiles(&self) -> CustomResult<Vec<String>> {
+        let repo = Path::new(self.repo);
+        let candidates = match self.repo_type {
+            RepoType::Node => vec![
+                "package.json".to_string(),
+                PackageManager::detect(repo).get_lockfile().to_string(),
+            ],
+            RepoType::Python => {
+                let mut files = vec!["pyproject.toml".to_string(), "poetry.lock".to_string()];
+                files.extend(get_requirements_files(repo)?);
+                files
+            }
+        };
+
+        Ok(candidates
+            .into_iter()
+            .filter(|file| repo.join(file).is_file())
+            .collect())
+    }
+
+    // Clean targets that exist and the installed packages or virtualenv
+    fn get_outputs(&self) -> Vec<String> {
+        let build_output = get_build_output(self.repo_name, self.repo, self.repo_type, self.python);
+        let mut outputs: Vec<String> = self
+            .clean_targets
+            .iter()
+            .map(|target| {
+                Path::new(&glob::Pattern::escape(self.repo))
+                    .join(target)
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .filter(|output| has_match(output))
+            .collect();
+        outputs.push(glob::Pattern::escape(&build_output.to_string_lossy()));
+
+        outputs
+    }
+
+    fn get_runtime(&self) -> CustomResult<String> {
+        match self.repo_type {
+            RepoType::Node => {
+                let package_manager = PackageManager::detect(Path::new(self.repo));
+                Ok(format!(
+                    "{} {} {}",
+                    self.get_version("node")?,
+                    package_manager.get_program(),
+                    self.get_version(package_manager.get_program())?
+                ))
+            }
+            RepoType::Python => self.get_version(&self.python.interpreter),
+        }
+    }
+
+    fn get_version(&self, program: &str) -> CustomResult<String> {
+        let output = Command::new(program)
+            .arg("--version")
+            .current_dir(self.repo)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            return Err(CustomError::CommandExecution(format!(
+                "Failed to get the version of {}",
+                program
+            )));
+        }
+        // Old pythons print the version to stderr
+        let version = [output.stdout, output.stderr].concat();
+
+        Ok(String::from_utf8_lossy(&version).trim().to_string())
+    }
+
+    fn get_settings(&self) -> Vec<String> {
+        let mut settings: Vec<String> = self
+            .clean_targets
+            .iter()
+            .map(|target| format!("clean={}", target))
+            .collect();
+        if let RepoType::Python = self.repo_type {
+            settings.push(format!("run_tests={}", self.python.run_tests));
+            settings.push(format!("compile_check={}", self.python.compile_check));
+        }
+
+        settings
+    }
+
+    fn run_git(&self, args: &[&str]) -> CustomResult<String> {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(self.repo)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            let logger = self.get_logger();
+            logger.error(format!("Failed to run git {:?} in repo: {}", args, self.repo).as_str());
+            logger.error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+
+            return Err(CustomError::CommandExecution(format!(
+                "Failed to run git {}",
+                args.join(" ")
+            )));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
+
+fn has_match(pattern: &str) -> bool {
+    glob::glob(pattern).is_ok_and(|mut paths| paths.any(|path| path.is_ok()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn test_is_fresh_needs_matching_fingerprint_and_build_outputs() {
+        let root = TempDir::new("build_cache");
+        fs::create_dir_all(root.join("api/node_modules")).unwrap();
+        fs::create_dir_all(root.join("api/dist")).unwrap();
+        let repo_name = String::from("api");
+        let repo = root.join("api").to_string_lossy().to_string();
+        let clean_targets = vec![
+            "node_modules".to_string(),
+            "dist".to_string(),
+            "coverage".to_string(),
+        ];
+        let python = PythonConfig::default();
+        let config = BuildCacheConfig {
+            enabled: true,
+            dir: root.join("cache").to_string_lossy().to_string(),
+        };
+        let cache = BuildCache {
+            repo_name: &repo_name,
+            repo: &repo,
+            repo_type: RepoType::Node,
+            clean_targets: &clean_targets,
+            python: &python,
+            config: &config,
+        };
+        let fingerprint = BuildFingerprint {
+            tree: "4b825dc642cb6eb9a060e54bf8d69288fbee4904".to_string(),
+            lockfiles: BTreeMap::from([("package-lock.json".to_string(), "abc".to_string())]),
+            runtime: "v20.11.0 npm 10.2.4".to_string(),
+            settings: vec!["clean=node_modules".to_string()],
+        };
+
+        assert!(!cache.is_fresh(&fingerprint).unwrap());
+        // `coverage` is never built, it is not expected to be there
+        cache.store(&fingerprint).unwrap();
+        assert!(cache.is_fresh(&fingerprint).unwrap());
+        let upgraded = BuildFingerprint {
+            runtime: "v20.11.0 npm 11.0.0".to_string(),
+            ..fingerprint.clone()
+        };
+        assert!(!cache.is_fresh(&upgraded).unwrap());
+        fs::remove_dir_all(root.join("api/dist")).unwrap();
+        assert!(!cache.is_fresh(&fingerprint).unwrap());
+    }
+
+    #[test]
+    fn test_get_outputs_matches_repo_path_literally() {
+        let root = TempDir::new("build_cache_escape");
+        fs::create_dir_all(root.join("api[1]/dist")).unwrap();
+        fs::create_dir_all(root.join("api1/coverage")).unwrap();
+        let repo_name = String::from("api[1]");
+        let repo = root.join("api[1]").to_string_lossy().to_string();
+        let clean_targets = vec!["dist".to_string(), "coverage".to_string()];
+        let python = PythonConfig::default();
+        let config = BuildCacheConfig {
+            enabled: true,
+            dir: root.join("cache").to_string_lossy().to_string(),
+        };
+        let cache = BuildCache {
+            repo_name: &repo_name,
+            repo: &repo,
+            repo_type: RepoType::Node,
+            clean_targets: &clean_targets,
+            python: &python,
+            config: &config,
+        };
+
+        let outputs = cache.get_outputs();
+
+        assert_eq!(outputs.len(), 2);
+        assert!(has_match(&outputs[0]));
+        assert!(outputs[0].ends_with("api[[]1[]]/dist"));
+    }
+}
@@ -0,0 +1,248 @@
+use std::fs;
+use std::path::Path;
+
+use chrono::Utc;
+use regex::Regex;
+
+use crate::{
+    config::{ChangelogConfig, ChangelogFormat},
+    custom_error::{CustomError, CustomResult},
+    logger::LoggerTrait,
+    workers::history::Commit,
+};
+
+const CHANGELOG_HEADER: &str = "# Changelog\n\nAll notable changes to this project will be documented in this file.\n\nThe format is based on [Keep a Changelog](https://keepachangelog.com/en/1.1.0/).\n\n";
+const DEFAULT_TEMPLATE: &str = "## {version} - {date}\n\n{entries}\n";
+
+pub struct ChangelogWriter<'repo> {
+    pub path: &'repo String,
+    pub version: &'repo String,
+    pub history: &'repo [Commit],
+    pub notes: &'repo [String],
+    pub config: &'repo ChangelogConfig,
+}
+
+impl LoggerTrait for ChangelogWriter<'_> {}
+impl ChangelogWriter<'_> {
+    pub fn write(&self) -> CustomResult<()> {
+        let logger = self.get_logger();
+        if !self.config.enabled {
+            logger.debug(format!("Changelog is disabled for repo: {}", self.path).as_str());
+            return Ok(());
+        }
+
+        let file_path = Path::new(self.path).join(&self.config.path);
+        logger.info(format!("Updating changelog: {}", file_path.display()).as_str());
+
+        let existing = match fs::read_to_string(&file_path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(CustomError::FileSystem(err.to_string())),
+        };
+
+        let section = self.render_section(&Utc::now().format("%Y-%m-%d").to_string());
+        if self.contains_version(&existing) {
+            logger.warn(
+                format!(
+                    "Changelog already has a section for version {}: {}",
+                    self.version,
+                    file_path.display()
+                )
+                .as_str(),
+            );
+            return Ok(());
+        }
+
+        let updated = insert_section(&existing, &section);
+        fs::write(&file_path, updated).map_err(|err| CustomError::FileSystem(err.to_string()))?;
+        logger.info(format!("Updated changelog: {}", file_path.display()).as_str());
+
+        Ok(())
+    }
+
+    fn render_section(&self, date: &str) -> String {
+        let entries = self.get_entries();
+        match self.config.format {
+            ChangelogFormat::KeepAChangelog => {
+                render_keep_a_changelog_section(self.version, date, &entries)
+            }
+            ChangelogFormat::Custom => {
+                let template = self.config.template.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+                let entries = entries
+                    .iter()
+                    .map(|entry| format!("- {}", entry))
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                let mut section = template
+                    .replace("{version}", self.version)
+                    .replace("{date}", date)
+                    .replace("{entries}", &entries);
+                if !section.ends_with('\n') {
+                    section.push('\n');
+                }
+                section
+            }
+        }
+    }
+
+    fn contains_version(&self, existing: &str) -> bool {
+        match self.config.format {
+            ChangelogFormat::KeepAChangelog => {
+                let heading = format!("## [{}]", self.version);
+                existing.lines().any(|line| line.starts_with(&heading))
+            }
+            ChangelogFormat::Custom => {
+                let template = self.config.template.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+                has_custom_heading(existing, template, self.version)
+            }
+        }
+    }
+
+    fn get_entries(&self) -> Vec<String> {
+        self.history
+            .iter()
+            .map(|commit| commit.subject.trim().to_string())
+            .chain(self.notes.iter().cloned())
+            .filter(|entry| !entry.is_empty())
+            .collect()
+    }
+}
+
+fn render_keep_a_changelog_section(version: &str, date: &str, entries: &[String]) -> String {
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    let mut fixed = Vec::new();
+
+    for entry in entries {
+        let prefix = entry.split(':').next().unwrap_or_default().to_lowercase();
+        if prefix.starts_with("feat") {
+            added.push(entry);
+        } else if prefix.starts_with("fix") {
+            fixed.push(entry);
+        } else {
+            changed.push(entry);
+        }
+    }
+
+    let mut section = format!("## [{}] - {}\n", version, date);
+    for (title, group) in [("Added", added), ("Changed", changed), ("Fixed", fixed)] {
+        if group.is_empty() {
+            continue;
+        }
+        section.push_str(&format!("\n### {}\n\n", title));
+        for entry in group {
+            section.push_str(&format!("- {}\n", entry));
+        }
+    }
+
+    section
+}
+
+// Looks for the template line with `{version}` rendered for this version. The date and
+// any other placeholder on that line may differ, a re-run on another day is the same release.
+fn has_custom_heading(existing: &str, template: &str, version: &str) -> bool {
+    let Some(heading) = template.lines().find(|line| line.contains("{version}")) else {
+        return false;
+    };
+    let pattern = regex::escape(heading)
+        .replace(r"\{version\}", &regex::escape(version))
+        .replace(r"\{date\}", ".*")
+        .replace(r"\{entries\}", ".*");
+    let Ok(heading) = Regex::new(&format!("^{}$", pattern)) else {
+        return false;
+    };
+
+    existing
+        .lines()
+        .any(|line| heading.is_match(line.trim_end()))
+}
+
+// Inserts the section above the latest released version, keeping the header and
+// the `Unreleased` section (and any other hand-written content) untouched.
+fn insert_section(existing: &str, section: &str) -> String {
+    if existing.trim().is_empty() {
+        return format!("{}{}", CHANGELOG_HEADER, section);
+    }
+
+    let mut offset = 0;
+    for line in existing.split_inclusive('\n') {
+        if line.starts_with("## ") && !line.to_lowercase().contains("unreleased") {
+            return format!(
+                "{}{}\n{}",
+                &existing[..offset],
+                section,
+                &existing[offset..]
+            );
+        }
+        offset += line.len();
+    }
+
+    let separator = if existing.ends_with("\n\n") {
+        ""
+    } else if existing.ends_with('\n') {
+        "\n"
+    } else {
+        "\n\n"
+    };
+    format!("{}{}{}", existing, separator, section)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keep_a_changelog_section_groups_entries() {
+        let entries = vec![
+            String::from("feat: add export"),
+            String::from("fix(api): handle nulls"),
+            String::from("update readme"),
+        ];
+
+        let section = render_keep_a_changelog_section("1.0.1", "2024-01-01", &entries);
+
+        assert_eq!(
+            section,
+            "## [1.0.1] - 2024-01-01\n\n### Added\n\n- feat: add export\n\n### Changed\n\n- update readme\n\n### Fixed\n\n- fix(api): handle nulls\n"
+        );
+    }
+
+    #[test]
+    fn test_custom_heading_matches_version_on_any_date() {
+        let template = "## {version} ({date})\n\n{entries}\n";
+        let existing = "# Changelog\n\n## 5.9.25c (2024-01-01)\n\n- fix: nulls\n";
+
+        assert!(has_custom_heading(existing, template, "5.9.25c"));
+        assert!(!has_custom_heading(existing, template, "5.9.25"));
+        assert!(!has_custom_heading(existing, template, "5.9.25d"));
+    }
+
+    #[test]
+    fn test_insert_section_into_empty_file_adds_header() {
+        let result = insert_section("", "## [1.0.1] - 2024-01-01\n");
+
+        assert_eq!(
+            result,
+            format!("{}## [1.0.1] - 2024-01-01\n", CHANGELOG_HEADER)
+        );
+    }
+
+    #[test]
+    fn test_insert_section_keeps_unreleased_and_existing_entries() {
+        let existing = "# Changelog\n\n## [Unreleased]\n\n- manual note\n\n## [1.0.0] - 2023-12-01\n\n- initial\n";
+
+        let result = insert_section(existing, "## [1.0.1] - 2024-01-01\n");
+
+        assert_eq!(
+            result,
+            "# Changelog\n\n## [Unreleased]\n\n- manual note\n\n## [1.0.1] - 2024-01-01\n\n## [1.0.0] - 2023-12-01\n\n- initial\n"
+        );
+    }
+
+    #[test]
+    fn test_insert_section_appends_when_no_versions() {
+        let result = insert_section("# Changelog", "## [1.0.1] - 2024-01-01\n");
+
+        assert_eq!(result, "# Changelog\n\n## [1.0.1] - 2024-01-01\n");
+    }
+}
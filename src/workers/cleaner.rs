@@ -0,0 +1,231 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    config::{RepoSettings, RepoType},
+    custom_error::{CustomError, CustomResult},
+    logger::LoggerTrait,
+};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CleanResult {
+    // Removed paths relative to the repo root
+    pub removed: Vec<String>,
+    pub freed_bytes: u64,
+}
+
+pub struct RepoCleaner<'repo> {
+    pub repo: &'repo String,
+    // Paths or globs relative to the repo root
+    pub targets: &'repo [String],
+}
+
+impl LoggerTrait for RepoCleaner<'_> {}
+impl RepoCleaner<'_> {
+    pub fn clean(&self) -> CustomResult<CleanResult> {
+        let logger = self.get_logger();
+        logger.debug(format!("Cleaning repo: {}. Targets: {:?}", self.repo, self.targets).as_str());
+        let root = fs::canonicalize(self.repo).map_err(|err| {
+            CustomError::FileSystem(format!("Could not resolve repo `{}`: {}", self.repo, err))
+        })?;
+
+        let mut result = CleanResult::default();
+        for target in self.targets {
+            for path in self.resolve_target(&root, target)? {
+                // Nested matches of a removed folder are gone already
+                if fs::symlink_metadata(&path).is_err() {
+                    continue;
+                }
+                let freed_bytes = get_size(&path)?;
+                remove(&path)?;
+                let relative = path.strip_prefix(&root).unwrap_or(&path);
+                logger.debug(
+                    format!("Removed {} ({} bytes)", relative.display(), freed_bytes).as_str(),
+                );
+                result.removed.push(relative.to_string_lossy().to_string());
+                result.freed_bytes += freed_bytes;
+            }
+        }
+        logger.info(
+            format!(
+                "Cleaned repo: {}. Removed: {:?}, freed {} bytes",
+                self.repo, result.removed, result.freed_bytes
+            )
+            .as_str(),
+        );
+
+        Ok(result)
+    }
+
+    // Expands the target and makes sure every match stays inside the repo.
+    // Symlinks are removed themselves, never the files they point to.
+    fn resolve_target(&self, root: &Path, target: &str) -> CustomResult<Vec<PathBuf>> {
+        check_target(target)?;
+        // Only the target is a glob, the repo path is matched literally
+        let pattern = Path::new(&glob::Pattern::escape(&root.to_string_lossy())).join(target);
+        let paths = glob::glob(&pattern.to_string_lossy()).map_err(|err| {
+            CustomError::ConfigParsingError(format!("Invalid clean target `{}`: {}", target, err))
+        })?;
+
+        let mut resolved = Vec::new();
+        for path in paths {
+            let path = path.map_err(|err| CustomError::FileSystem(err.to_string()))?;
+            let parent = path.parent().ok_or_else(|| {
+                CustomError::FileSystem(format!("Refusing to remove `{}`", path.display()))
+            })?;
+            let parent =
+                fs::canonicalize(parent).map_err(|err| CustomError::FileSystem(err.to_string()))?;
+            if !parent.starts_with(root) || path.file_name() == Some(".git".as_ref()) {
+                return Err(CustomError::FileSystem(format!(
+                    "Clean target `{}` resolves outside of the repo or to its git folder: {}",
+                    target,
+                    path.display()
+                )));
+            }
+            if let Some(name) = path.file_name() {
+                resolved.push(parent.join(name));
+            }
+        }
+
+        Ok(resolved)
+    }
+}
+
+pub fn get_clean_targets(repo_type: RepoType, settings: Option<&RepoSettings>) -> Vec<String> {
+    if let Some(clean) = settings.and_then(|settings| settings.clean.as_ref()) {
+        return clean.clone();
+    }
+    match repo_type {
+        RepoType::Node => vec!["node_modules".to_string(), "dist".to_string()],
+        RepoType::Python => Vec::new(),
+    }
+}
+
+// Only plain relative paths below the repo root are allowed
+fn check_target(target: &str) -> CustomResult<()> {
+    let path = Path::new(target);
+    let is_below_root = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if target.trim().is_empty() || !is_below_root {
+        return Err(CustomError::ConfigParsingError(format!(
+            "Clean target `{}` must be a relative path inside the repo",
+            target
+        )));
+    }
+
+    Ok(())
+}
+
+fn get_size(path: &Path) -> CustomResult<u64> {
+    let metadata =
+        fs::symlink_metadata(path).map_err(|err| CustomError::FileSystem(err.to_string()))?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut size = 0;
+    for entry in fs::read_dir(path).map_err(|err| CustomError::FileSystem(err.to_string()))? {
+        let entry = entry.map_err(|err| CustomError::FileSystem(err.to_string()))?;
+        size += get_size(&entry.path())?;
+    }
+
+    Ok(size)
+}
+
+fn remove(path: &Path) -> CustomResult<()> {
+    let metadata =
+        fs::symlink_metadata(path).map_err(|err| CustomError::FileSystem(err.to_string()))?;
+    let result = if metadata.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+
+    result.map_err(|err| {
+        CustomError::FileSystem(format!("Could not remove `{}`: {}", path.display(), err))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    fn get_repo(name: &str) -> TempDir {
+        let repo = TempDir::new(&format!("cleaner_{}", name));
+        fs::create_dir_all(repo.join("node_modules/pkg")).unwrap();
+        fs::write(repo.join("node_modules/pkg/index.js"), "12345").unwrap();
+        fs::create_dir_all(repo.join("dist")).unwrap();
+        fs::write(repo.join("dist/app.js"), "123").unwrap();
+        fs::write(repo.join("package.json"), "{}").unwrap();
+        repo
+    }
+
+    #[test]
+    fn test_clean_removes_targets_and_counts_bytes() {
+        let repo = get_repo("targets");
+        let repo_path = repo.to_path_string();
+        let targets = vec![
+            "node_modules".to_string(),
+            "dist".to_string(),
+            "build".to_string(),
+        ];
+        let cleaner = RepoCleaner {
+            repo: &repo_path,
+            targets: &targets,
+        };
+
+        let result = cleaner.clean().unwrap();
+
+        assert_eq!(result.removed, vec!["node_modules", "dist"]);
+        assert_eq!(result.freed_bytes, 8);
+        assert!(!repo.join("node_modules").exists());
+        assert!(repo.join("package.json").exists());
+    }
+
+    #[test]
+    fn test_clean_matches_repo_path_literally() {
+        let root = TempDir::new("cleaner_escape");
+        for repo in ["api[1]", "api1"] {
+            fs::create_dir_all(root.join(repo).join("dist")).unwrap();
+        }
+        let repo_path = root.join("api[1]").to_string_lossy().to_string();
+        let targets = vec!["dist".to_string()];
+        let cleaner = RepoCleaner {
+            repo: &repo_path,
+            targets: &targets,
+        };
+
+        let result = cleaner.clean().unwrap();
+
+        assert_eq!(result.removed, vec!["dist"]);
+        assert!(!root.join("api[1]/dist").exists());
+        assert!(root.join("api1/dist").exists());
+    }
+
+    #[test]
+    fn test_clean_refuses_targets_outside_of_repo() {
+        for target in ["../other", "/tmp", "", ".", "dist/../.."] {
+            assert!(check_target(target).is_err(), "{}", target);
+        }
+        assert!(check_target("packages/*/dist").is_ok());
+    }
+
+    #[test]
+    fn test_clean_refuses_git_folder() {
+        let repo = get_repo("git");
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        let repo_path = repo.to_path_string();
+        let targets = vec![".*".to_string()];
+        let cleaner = RepoCleaner {
+            repo: &repo_path,
+            targets: &targets,
+        };
+
+        assert!(cleaner.clean().is_err());
+        assert!(repo.join(".git").exists());
+    }
+}
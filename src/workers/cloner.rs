@@ -0,0 +1,334 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use crate::{
+    command_log::LoggedCommand,
+    config::{CloneConfig, Data},
+    custom_error::{CustomError, CustomResult},
+    logger::{Logger, LoggerTrait},
+};
+
+// Deepening steps before a shallow clone is unshallowed
+const MAX_DEEPEN_STEPS: u32 = 10;
+
+pub struct RepoCloner<'repo> {
+    pub root: &'repo String,
+    pub repo_name: &'repo String,
+    pub branch: &'repo String,
+    pub release_branch: &'repo String,
+    pub config: &'repo Option<CloneConfig>,
+}
+
+impl LoggerTrait for RepoCloner<'_> {}
+impl RepoCloner<'_> {
+    pub fn is_present(&self) -> bool {
+        Path::new(self.root)
+            .join(self.repo_name)
+            .join(".git")
+            .exists()
+    }
+
+    // Clones the repo if it is missing. Returns `true` when a clone was made.
+    pub fn ensure_present(&self) -> CustomResult<bool> {
+        let cloned = !self.is_present();
+        if cloned {
+            self.clone_repo()?;
+        }
+        self.complete_history()?;
+
+        Ok(cloned)
+    }
+
+    pub fn sync(&self) -> CustomResult<()> {
+        if self.ensure_present()? {
+            return Ok(());
+        }
+        self.fetch_repo()?;
+        self.complete_history()
+    }
+
+    // Deepens a shallow clone until the release tag and merge-base the history, describe
+    // and back-merge steps rely on are reachable. A full clone is left as it is.
+    fn complete_history(&self) -> CustomResult<()> {
+        let logger = self.get_logger();
+        let depth = match self.config.as_ref().and_then(|config| config.depth) {
+            Some(depth) => depth,
+            None => return Ok(()),
+        };
+        let is_shallow = self.run_git(
+            &["rev-parse", "--is-shallow-repository"],
+            "Failed to check shallow clone",
+        )?;
+        if String::from_utf8_lossy(&is_shallow.stdout).trim() != "true" {
+            return Ok(());
+        }
+
+        let deepen = format!("--deepen={}", depth);
+        for _ in 0..MAX_DEEPEN_STEPS {
+            if self.has_release_history()? {
+                return Ok(());
+            }
+            logger.info(format!("Deepening shallow clone of repo: {}", self.repo_name).as_str());
+            self.run_git(
+                &["fetch", "origin", &deepen],
+                "Failed to deepen shallow clone",
+            )?;
+        }
+        if self.has_release_history()? {
+            return Ok(());
+        }
+
+        logger.warn(
+            format!(
+                "No release tag or merge-base within the shallow clone of repo: {}. Fetching the full history",
+                self.repo_name
+            )
+            .as_str(),
+        );
+        self.run_git(
+            &["fetch", "origin", "--unshallow"],
+            "Failed to unshallow clone",
+        )?;
+
+        Ok(())
+    }
+
+    fn has_release_history(&self) -> CustomResult<bool> {
+        let target = format!("origin/{}", self.branch);
+        let has_tag = self.succeeds(&[
+            "describe",
+            "--tags",
+            "--abbrev=0",
+            "--match",
+            "release/*",
+            &target,
+        ])?;
+        let release = format!("origin/{}", self.release_branch);
+        let has_release_branch = self.succeeds(&["rev-parse", "--verify", "-q", &release])?;
+        let has_merge_base =
+            !has_release_branch || self.succeeds(&["merge-base", &target, &release])?;
+
+        Ok(has_tag && has_merge_base)
+    }
+
+    fn succeeds(&self, args: &[&str]) -> CustomResult<bool> {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(Path::new(self.root).join(self.repo_name))
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+
+        Ok(output.status.success())
+    }
+
+    fn run_git(&self, args: &[&str], error: &str) -> CustomResult<Output> {
+        let logger = self.get_logger();
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(Path::new(self.root).join(self.repo_name))
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            logger.error(format!("{} for repo: {}", error, self.repo_name).as_str());
+            logger.error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+
+            return Err(CustomError::CommandExecution(error.to_string()));
+        }
+
+        Ok(output)
+    }
+
+    fn clone_repo(&self) -> CustomResult<()> {
+        let logger = self.get_logger();
+        let config = match self.config {
+            Some(config) => config,
+            None => {
+                return Err(CustomError::ConfigParsingError(format!(
+                    "Repo {} is missing in {} and no clone.remote_url_template is configured",
+                    self.repo_name, self.root
+                )))
+            }
+        };
+        let url = config.remote_url_template.replace("{name}", self.repo_name);
+        logger.info(format!("Cloning repo: {} from {}", self.repo_name, url).as_str());
+
+        fs::create_dir_all(self.root).map_err(|err| CustomError::FileSystem(err.to_string()))?;
+        let mut command = Command::new("git");
+        command.arg("clone");
+        // Every branch is fetched, the release branch is needed next to the source branch
+        if let Some(depth) = config.depth {
+            command
+                .arg("--depth")
+                .arg(depth.to_string())
+                .arg("--no-single-branch");
+        }
+        if config.partial {
+            command.arg("--filter=blob:none");
+        }
+
+        let output = command
+            .arg(&url)
+            .arg(self.repo_name)
+            .current_dir(self.root)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            logger.error(format!("Failed to clone repo: {}", self.repo_name).as_str());
+            logger.error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+
+            return Err(CustomError::CommandExecution(
+                "Failed to clone repo".to_string(),
+            ));
+        }
+        logger.info(format!("Cloned repo: {}", self.repo_name).as_str());
+
+        Ok(())
+    }
+
+    fn fetch_repo(&self) -> CustomResult<()> {
+        let logger = self.get_logger();
+        let path = Path::new(self.root).join(self.repo_name);
+        logger.info(format!("Fetching repo: {}", path.display()).as_str());
+        let output = Command::new("git")
+            .arg("fetch")
+            .arg("origin")
+            .arg("--prune")
+            .current_dir(&path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            logger.error(format!("Failed to fetch repo: {}", path.display()).as_str());
+            logger.error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+
+            return Err(CustomError::CommandExecution(
+                "Failed to fetch repo".to_string(),
+            ));
+        }
+        logger.info(format!("Fetched repo: {}", path.display()).as_str());
+
+        Ok(())
+    }
+}
+
+pub fn sync(config: &Data) -> CustomResult<()> {
+    let logger = Logger::new();
+    let repos = config.repos.get_repos_list()?;
+    logger.info(format!("Syncing {} repos into {}", repos.len(), config.root).as_str());
+
+    let mut errors: Vec<(String, String)> = Vec::new();
+    for repo in repos.iter() {
+        let cloner = RepoCloner {
+            root: &config.root,
+            repo_name: repo,
+            branch: &config.git.branch,
+            release_branch: &config.git.release_branch,
+            config: &config.clone,
+        };
+        if let Err(e) = cloner.sync() {
+            errors.push((repo.clone(), e.to_string()));
+        }
+    }
+
+    if !errors.is_empty() {
+        logger.warn(format!("Sync errors: {:#?}", errors).as_str());
+        let repos: Vec<String> = errors.into_iter().map(|(repo, _)| repo).collect();
+        return Err(CustomError::CommandExecution(format!(
+            "Failed to sync repos: {}",
+            repos.join(", ")
+        )));
+    }
+    logger.info("Sync finished");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{commit_file, git, init_git_repo, TempDir};
+
+    fn get_config(dir: &Path, depth: u32) -> Option<CloneConfig> {
+        Some(CloneConfig {
+            remote_url_template: format!("file://{}/{{name}}", dir.display()),
+            depth: Some(depth),
+            partial: false,
+        })
+    }
+
+    #[test]
+    fn test_shallow_clone_reaches_release_history() {
+        let dir = TempDir::new("cloner_shallow");
+        let work = init_git_repo(&dir);
+        commit_file(&work, "a.txt", "1\n", "feat: one");
+        git(&work, &["tag", "release/1.0.0"]);
+        git(
+            &work,
+            &["push", "-q", "origin", "main:release", "release/1.0.0"],
+        );
+        for i in 0..6 {
+            commit_file(&work, "a.txt", &format!("{}\n", i), "feat: more");
+        }
+        git(&work, &["push", "-q", "origin", "main"]);
+        let root = dir.join("root").to_string_lossy().to_string();
+        let names = [
+            String::from("origin.git"),
+            String::from("main"),
+            String::from("release"),
+        ];
+        let config = get_config(&dir, 2);
+        let cloner = RepoCloner {
+            root: &root,
+            repo_name: &names[0],
+            branch: &names[1],
+            release_branch: &names[2],
+            config: &config,
+        };
+
+        assert!(cloner.ensure_present().unwrap());
+
+        let clone = Path::new(&root).join("origin.git");
+        assert_eq!(
+            git(&clone, &["rev-parse", "--is-shallow-repository"]),
+            "true"
+        );
+        assert_eq!(
+            git(&clone, &["describe", "--tags", "--abbrev=0", "origin/main"]),
+            "release/1.0.0"
+        );
+        git(&clone, &["merge-base", "origin/main", "origin/release"]);
+    }
+
+    #[test]
+    fn test_shallow_clone_without_release_is_unshallowed() {
+        let dir = TempDir::new("cloner_unshallow");
+        let work = init_git_repo(&dir);
+        for i in 0..30 {
+            commit_file(&work, "a.txt", &format!("{}\n", i), "feat: more");
+        }
+        git(&work, &["push", "-q", "origin", "main"]);
+        let root = dir.join("root").to_string_lossy().to_string();
+        let names = [
+            String::from("origin.git"),
+            String::from("main"),
+            String::from("release"),
+        ];
+        let config = get_config(&dir, 1);
+        let cloner = RepoCloner {
+            root: &root,
+            repo_name: &names[0],
+            branch: &names[1],
+            release_branch: &names[2],
+            config: &config,
+        };
+
+        cloner.sync().unwrap();
+
+        let clone = Path::new(&root).join("origin.git");
+        assert_eq!(
+            git(&clone, &["rev-parse", "--is-shallow-repository"]),
+            "false"
+        );
+        assert_eq!(git(&clone, &["rev-list", "--count", "origin/main"]), "31");
+    }
+}
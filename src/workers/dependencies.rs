@@ -0,0 +1,523 @@
+use std::collections::HashMap;
+use std::fs::{read_to_string, write};
+use std::path::Path;
+use std::process::Command;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::{
+    command_log::{self, LoggedCommand},
+    custom_error::{CustomError, CustomResult},
+    logger::{Logger, LoggerTrait},
+    workers::rebuilder::PackageManager,
+};
+
+const DEPENDENCY_SECTIONS: [&str; 2] = ["dependencies", "devDependencies"];
+const PUBLISH_POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug)]
+struct PackageManifest {
+    name: String,
+    dependencies: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DependencyUpdate {
+    pub package: String,
+    pub from: String,
+    pub to: String,
+}
+
+// Graph of configured repos built from their `package.json` files. Repos without
+// a readable manifest are kept as standalone nodes. The manifests are read from
+// `origin/<branch>`, the checkout on disk may be on any branch before the run switches it.
+#[derive(Debug)]
+pub struct DependencyGraph {
+    manifests: HashMap<String, PackageManifest>,
+}
+
+impl LoggerTrait for DependencyGraph {}
+impl DependencyGraph {
+    pub fn build(root: &str, repos: &[String], branch: &str) -> CustomResult<Self> {
+        let logger = Logger::new();
+        let mut manifests = HashMap::new();
+
+        for repo in repos {
+            let content = match read_remote_manifest(&Path::new(root).join(repo), branch) {
+                Some(content) => content,
+                None => {
+                    logger.debug(format!("No package.json found for repo: {}", repo).as_str());
+                    continue;
+                }
+            };
+            let json: serde_json::Value = serde_json::from_str(&content)
+                .map_err(|err| CustomError::DependencyGraph(format!("{}: {}", repo, err)))?;
+
+            let name = match json["name"].as_str() {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let mut dependencies = Vec::new();
+            for section in DEPENDENCY_SECTIONS.iter() {
+                if let Some(deps) = json[section].as_object() {
+                    dependencies.extend(deps.keys().cloned());
+                }
+            }
+
+            manifests.insert(repo.clone(), PackageManifest { name, dependencies });
+        }
+
+        Ok(Self { manifests })
+    }
+
+    pub fn get_package_name(&self, repo: &str) -> Option<&String> {
+        self.manifests.get(repo).map(|manifest| &manifest.name)
+    }
+
+    // Orders repos so that every library comes before its consumers. A library is pulled
+    // ahead of the first repo that needs it, every other repo keeps its configured order.
+    pub fn sort_repos(&self, repos: &[String]) -> CustomResult<Vec<String>> {
+        let package_owners: HashMap<&String, &String> = self
+            .manifests
+            .iter()
+            .map(|(repo, manifest)| (&manifest.name, repo))
+            .collect();
+
+        let mut sorted: Vec<String> = Vec::with_capacity(repos.len());
+        let mut visiting: Vec<String> = Vec::new();
+        for repo in repos {
+            self.visit_repo(repo, repos, &package_owners, &mut visiting, &mut sorted)?;
+        }
+
+        self.get_logger()
+            .debug(format!("Repos in dependency order: {:?}", sorted).as_str());
+
+        Ok(sorted)
+    }
+
+    // Places the configured dependencies of a repo before the repo itself
+    fn visit_repo(
+        &self,
+        repo: &String,
+        repos: &[String],
+        package_owners: &HashMap<&String, &String>,
+        visiting: &mut Vec<String>,
+        sorted: &mut Vec<String>,
+    ) -> CustomResult<()> {
+        if sorted.contains(repo) {
+            return Ok(());
+        }
+        if visiting.contains(repo) {
+            return Err(CustomError::DependencyGraph(format!(
+                "Dependency cycle between repos: {:?}",
+                visiting
+            )));
+        }
+
+        visiting.push(repo.clone());
+        for dependency in self.get_internal_dependencies(repo, package_owners) {
+            if dependency != *repo && repos.contains(&dependency) {
+                self.visit_repo(&dependency, repos, package_owners, visiting, sorted)?;
+            }
+        }
+        visiting.pop();
+        sorted.push(repo.clone());
+
+        Ok(())
+    }
+
+    fn get_internal_dependencies(
+        &self,
+        repo: &str,
+        package_owners: &HashMap<&String, &String>,
+    ) -> Vec<String> {
+        match self.manifests.get(repo) {
+            Some(manifest) => manifest
+                .dependencies
+                .iter()
+                .filter_map(|dependency| package_owners.get(dependency))
+                .map(|owner| owner.to_string())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+fn read_remote_manifest(path: &Path, branch: &str) -> Option<String> {
+    let fetched = Command::new("git")
+        .args(["fetch", "origin", branch])
+        .current_dir(path)
+        .logged_output()
+        .is_ok_and(|output| output.status.success());
+    if !fetched {
+        return None;
+    }
+
+    let output = Command::new("git")
+        .arg("show")
+        .arg(format!("origin/{}:package.json", branch))
+        .current_dir(path)
+        .logged_output()
+        .ok()
+        .filter(|output| output.status.success())?;
+
+    Some(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+pub struct DependencyUpdater<'repo> {
+    pub path: &'repo String,
+    pub released: &'repo HashMap<String, String>,
+}
+
+impl LoggerTrait for DependencyUpdater<'_> {}
+impl DependencyUpdater<'_> {
+    pub fn get_updates(&self) -> CustomResult<Vec<DependencyUpdate>> {
+        let logger = self.get_logger();
+        let content = match read_to_string(self.get_manifest_path()) {
+            Ok(content) => content,
+            Err(_) => return Ok(Vec::new()),
+        };
+        let json: serde_json::Value = serde_json::from_str(&content)
+            .map_err(|err| CustomError::DependencyGraph(err.to_string()))?;
+
+        let mut updates: Vec<DependencyUpdate> = Vec::new();
+        for section in DEPENDENCY_SECTIONS.iter() {
+            let deps = match json[section].as_object() {
+                Some(deps) => deps,
+                None => continue,
+            };
+            for (package, version) in self.released.iter() {
+                let current = match deps.get(package).and_then(|spec| spec.as_str()) {
+                    Some(current) => current,
+                    None => continue,
+                };
+                let next = match get_next_spec(current, version) {
+                    Some(next) => next,
+                    None => {
+                        logger.warn(
+                            format!(
+                                "Skipping unsupported version spec '{}' for {} in repo: {}",
+                                current, package, self.path
+                            )
+                            .as_str(),
+                        );
+                        continue;
+                    }
+                };
+                if next != current && !updates.iter().any(|update| update.package == *package) {
+                    updates.push(DependencyUpdate {
+                        package: package.clone(),
+                        from: current.to_string(),
+                        to: next,
+                    });
+                }
+            }
+        }
+
+        Ok(updates)
+    }
+
+    // Released packages are published by the pipeline of the tag, the lockfile can only
+    // be updated once the registry has them
+    pub fn wait_for_publication(
+        &self,
+        updates: &[DependencyUpdate],
+        timeout: Duration,
+    ) -> CustomResult<()> {
+        let logger = self.get_logger();
+        let started_at = Instant::now();
+        for update in updates {
+            let version = self.released.get(&update.package).ok_or_else(|| {
+                CustomError::DependencyGraph(format!("{} was not released", update.package))
+            })?;
+            while !self.is_published(&update.package, version)? {
+                if command_log::is_cancelled() {
+                    return Err(CustomError::Cancelled(format!(
+                        "Cancelled while waiting for {}@{}",
+                        update.package, version
+                    )));
+                }
+                if started_at.elapsed() >= timeout {
+                    return Err(CustomError::DependencyGraph(format!(
+                        "{}@{} was not published within {} seconds",
+                        update.package,
+                        version,
+                        timeout.as_secs()
+                    )));
+                }
+                logger.info(
+                    format!(
+                        "Waiting for {}@{} to be published for repo: {}",
+                        update.package, version, self.path
+                    )
+                    .as_str(),
+                );
+                sleep(PUBLISH_POLL_INTERVAL);
+            }
+        }
+
+        Ok(())
+    }
+
+    // `npm view` prints nothing for a version that doesn't exist yet
+    fn is_published(&self, package: &str, version: &str) -> CustomResult<bool> {
+        let output = Command::new("npm")
+            .arg("view")
+            .arg(format!("{}@{}", package, version))
+            .arg("version")
+            .current_dir(self.path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+
+        Ok(output.status.success() && !String::from_utf8_lossy(&output.stdout).trim().is_empty())
+    }
+
+    pub fn apply(&self, updates: &[DependencyUpdate]) -> CustomResult<()> {
+        let logger = self.get_logger();
+        let manifest_path = self.get_manifest_path();
+        let content = read_to_string(&manifest_path)
+            .map_err(|err| CustomError::FileSystem(err.to_string()))?;
+        let mut json: serde_json::Value = serde_json::from_str(&content)
+            .map_err(|err| CustomError::DependencyGraph(err.to_string()))?;
+
+        for update in updates {
+            logger.info(
+                format!(
+                    "Updating dependency {} from {} to {} in repo: {}",
+                    update.package, update.from, update.to, self.path
+                )
+                .as_str(),
+            );
+            let mut changed = false;
+            for section in DEPENDENCY_SECTIONS.iter() {
+                let spec = json
+                    .get_mut(section)
+                    .and_then(|deps| deps.get_mut(&update.package));
+                if let Some(spec) = spec.filter(|spec| spec.as_str() == Some(&update.from)) {
+                    *spec = serde_json::Value::String(update.to.clone());
+                    changed = true;
+                }
+            }
+            if !changed {
+                return Err(CustomError::DependencyGraph(format!(
+                    "Dependency {} {} not found in {}",
+                    update.package, update.from, manifest_path
+                )));
+            }
+        }
+
+        // npm writes two spaces and a trailing newline as well
+        let mut updated = serde_json::to_string_pretty(&json)
+            .map_err(|err| CustomError::DependencyGraph(err.to_string()))?;
+        if content.ends_with('\n') {
+            updated.push('\n');
+        }
+        write(&manifest_path, updated).map_err(|err| CustomError::FileSystem(err.to_string()))?;
+
+        let package_manager = PackageManager::detect(Path::new(self.path));
+        if Path::new(self.path)
+            .join(package_manager.get_lockfile())
+            .exists()
+        {
+            self.update_lockfile(package_manager)?;
+        }
+
+        Ok(())
+    }
+
+    // Commits and pushes dependency updates for a consumer that is not released itself.
+    pub fn commit_updates(
+        &self,
+        updates: &[DependencyUpdate],
+        branch: &str,
+        disable_checks: bool,
+    ) -> CustomResult<()> {
+        let logger = self.get_logger();
+        logger.info(format!("Committing dependency updates in repo: {}", self.path).as_str());
+
+        self.run_git(&["add", "--all"], "Failed to add dependency updates")?;
+
+        let mut commit_args = vec!["commit", "-m"];
+        let message = get_commit_message(updates);
+        commit_args.push(&message);
+        if disable_checks {
+            commit_args.push("--no-verify");
+        }
+        self.run_git(&commit_args, "Failed to commit dependency updates")?;
+
+        let target = format!("HEAD:{}", branch);
+        let mut push_args = vec!["push", "origin", &target];
+        if disable_checks {
+            push_args.push("--no-verify");
+        }
+        self.run_git(&push_args, "Failed to push dependency updates")?;
+        logger.info(format!("Committed dependency updates in repo: {}", self.path).as_str());
+
+        Ok(())
+    }
+
+    fn update_lockfile(&self, package_manager: PackageManager) -> CustomResult<()> {
+        let logger = self.get_logger();
+        let lockfile = package_manager.get_lockfile();
+        logger.info(format!("Updating {} in repo: {}", lockfile, self.path).as_str());
+        let output = Command::new(package_manager.get_program())
+            .args(package_manager.get_lockfile_update_args(Path::new(self.path)))
+            .current_dir(self.path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            logger.error(format!("Failed to update lockfile in repo: {}", self.path).as_str());
+            logger.error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+
+            return Err(CustomError::CommandExecution(
+                "Failed to update lockfile in repo".to_string(),
+            ));
+        }
+        logger.info(format!("Updated {} in repo: {}", lockfile, self.path).as_str());
+
+        Ok(())
+    }
+
+    fn run_git(&self, args: &[&str], error: &str) -> CustomResult<()> {
+        let logger = self.get_logger();
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(self.path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            logger.error(format!("{}: {}", error, self.path).as_str());
+            logger.error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+
+            return Err(CustomError::CommandExecution(error.to_string()));
+        }
+
+        Ok(())
+    }
+
+    fn get_manifest_path(&self) -> String {
+        Path::new(self.path)
+            .join("package.json")
+            .to_string_lossy()
+            .to_string()
+    }
+}
+
+pub fn get_commit_message(updates: &[DependencyUpdate]) -> String {
+    let packages = updates
+        .iter()
+        .map(|update| format!("{}@{}", update.package, update.to))
+        .collect::<Vec<String>>()
+        .join(", ");
+    format!("chore(deps): bump {}", packages)
+}
+
+// Keeps the range operator of the current spec. Non-semver specs (git urls, tags,
+// workspaces) are not touched.
+fn get_next_spec(current: &str, version: &str) -> Option<String> {
+    let prefix: String = current
+        .chars()
+        .take_while(|ch| *ch == '^' || *ch == '~')
+        .collect();
+    let rest = &current[prefix.len()..];
+    if !rest.chars().next().is_some_and(|ch| ch.is_ascii_digit()) {
+        return None;
+    }
+
+    Some(format!("{}{}", prefix, version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+    use std::fs;
+
+    fn get_graph(entries: &[(&str, &str, &[&str])]) -> DependencyGraph {
+        let manifests = entries
+            .iter()
+            .map(|(repo, name, deps)| {
+                (
+                    repo.to_string(),
+                    PackageManifest {
+                        name: name.to_string(),
+                        dependencies: deps.iter().map(|dep| dep.to_string()).collect(),
+                    },
+                )
+            })
+            .collect();
+        DependencyGraph { manifests }
+    }
+
+    #[test]
+    fn test_sort_repos_puts_libraries_first() {
+        let graph = get_graph(&[
+            ("api", "@org/api", &["@org/common", "express"]),
+            ("common", "@org/common", &["@org/types"]),
+            ("types", "@org/types", &[]),
+        ]);
+        let repos = vec![
+            String::from("api"),
+            String::from("common"),
+            String::from("python-worker"),
+            String::from("types"),
+        ];
+
+        let sorted = graph.sort_repos(&repos).unwrap();
+
+        assert_eq!(sorted, vec!["types", "common", "api", "python-worker"]);
+    }
+
+    #[test]
+    fn test_sort_repos_fails_on_cycle() {
+        let graph = get_graph(&[("a", "a", &["b"]), ("b", "b", &["a"])]);
+
+        let result = graph.sort_repos(&[String::from("a"), String::from("b")]);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_apply_updates_parsed_manifest() {
+        let repo = TempDir::new("dependencies_apply");
+        fs::write(
+            repo.join("package.json"),
+            "{\n  \"name\": \"@org/api\",\n  \"dependencies\": {\"@org/common\" :\"^1.2.3\", \"express\": \"4.0.0\"}\n}\n",
+        )
+        .unwrap();
+        let path = repo.to_path_string();
+        let released = HashMap::new();
+        let updater = DependencyUpdater {
+            path: &path,
+            released: &released,
+        };
+        let update = DependencyUpdate {
+            package: String::from("@org/common"),
+            from: String::from("^1.2.3"),
+            to: String::from("^1.3.0"),
+        };
+
+        updater.apply(std::slice::from_ref(&update)).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(repo.join("package.json")).unwrap(),
+            "{\n  \"name\": \"@org/api\",\n  \"dependencies\": {\n    \"@org/common\": \"^1.3.0\",\n    \"express\": \"4.0.0\"\n  }\n}\n"
+        );
+        // The spec is not `^1.2.3` anymore
+        assert!(updater.apply(&[update]).is_err());
+    }
+
+    #[test]
+    fn test_get_next_spec_keeps_range_operator() {
+        assert_eq!(
+            get_next_spec("^1.2.3", "1.3.0"),
+            Some(String::from("^1.3.0"))
+        );
+        assert_eq!(
+            get_next_spec("~1.2.3", "1.3.0"),
+            Some(String::from("~1.3.0"))
+        );
+        assert_eq!(get_next_spec("1.2.3", "1.3.0"), Some(String::from("1.3.0")));
+        assert_eq!(get_next_spec("workspace:*", "1.3.0"), None);
+    }
+}
@@ -0,0 +1,139 @@
+use std::process::Command;
+use std::time::Instant;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    command_log::LoggedCommand,
+    config::{GatesConfig, PythonConfig, RepoSettings, RepoType},
+    custom_error::{CustomError, CustomResult},
+    logger::LoggerTrait,
+    workers::python::PythonRebuilder,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GateResult {
+    pub command: String,
+    pub passed: bool,
+    pub exit_code: Option<i32>,
+    pub duration_secs: u64,
+}
+
+// Runs the quality gate commands of a repo after the rebuild. Every gate runs,
+// so the report shows all failures at once.
+pub struct GateRunner<'repo> {
+    pub repo_name: &'repo String,
+    pub repo: &'repo String,
+    pub repo_type: RepoType,
+    pub commands: &'repo [String],
+    pub python: &'repo PythonConfig,
+}
+
+impl LoggerTrait for GateRunner<'_> {}
+impl GateRunner<'_> {
+    pub fn run(&self) -> CustomResult<Vec<GateResult>> {
+        let mut results = Vec::new();
+        for command in self.commands {
+            results.push(self.run_gate(command)?);
+        }
+
+        Ok(results)
+    }
+
+    fn run_gate(&self, script: &str) -> CustomResult<GateResult> {
+        let logger = self.get_logger();
+        logger.info(format!("Running quality gate in repo: {}. {}", self.repo, script).as_str());
+        let mut command = match self.repo_type {
+            // Python gates run in the virtualenv of the rebuild
+            RepoType::Python => PythonRebuilder {
+                repo_name: self.repo_name,
+                repo: self.repo,
+                config: self.python,
+            }
+            .get_shell_command(script)?,
+            RepoType::Node => {
+                let mut command = Command::new("sh");
+                command.arg("-c").arg(script);
+                command
+            }
+        };
+
+        let started_at = Instant::now();
+        let output = command
+            .current_dir(self.repo)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        let result = GateResult {
+            command: script.to_string(),
+            passed: output.status.success(),
+            exit_code: output.status.code(),
+            duration_secs: started_at.elapsed().as_secs(),
+        };
+        if result.passed {
+            logger.info(format!("Quality gate passed in repo: {}. {}", self.repo, script).as_str());
+        } else {
+            logger
+                .error(format!("Quality gate failed in repo: {}. {}", self.repo, script).as_str());
+            logger.error(format!("Output: {}", String::from_utf8_lossy(&output.stdout)).as_str());
+            logger.error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+        }
+
+        Ok(result)
+    }
+}
+
+// Repo settings win over the gates of the ecosystem
+pub fn get_gate_commands(
+    config: &GatesConfig,
+    repo_type: RepoType,
+    settings: Option<&RepoSettings>,
+) -> Vec<String> {
+    if let Some(gates) = settings.and_then(|settings| settings.gates.as_ref()) {
+        return gates.clone();
+    }
+    match repo_type {
+        RepoType::Node => config.node.clone(),
+        RepoType::Python => config.python.clone(),
+    }
+}
+
+pub fn get_failed_gates(results: &[GateResult]) -> Vec<&str> {
+    results
+        .iter()
+        .filter(|result| !result.passed)
+        .map(|result| result.command.as_str())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_records_every_gate() {
+        let repo = std::env::temp_dir().to_string_lossy().to_string();
+        let commands = vec![
+            "exit 3".to_string(),
+            "true".to_string(),
+            "echo lint failed >&2; false".to_string(),
+        ];
+        let repo_name = String::from("api");
+        let python = PythonConfig::default();
+        let runner = GateRunner {
+            repo_name: &repo_name,
+            repo: &repo,
+            repo_type: RepoType::Node,
+            commands: &commands,
+            python: &python,
+        };
+
+        let results = runner.run().unwrap();
+
+        let exit_codes: Vec<Option<i32>> = results.iter().map(|result| result.exit_code).collect();
+        assert_eq!(exit_codes, vec![Some(3), Some(0), Some(1)]);
+        assert_eq!(
+            get_failed_gates(&results),
+            vec!["exit 3", "echo lint failed >&2; false"]
+        );
+    }
+}
@@ -1,58 +1,357 @@
+use std::collections::{HashMap, HashSet};
 use std::process::Command;
 
+use chrono::{DateTime, FixedOffset};
+use serde_derive::{Deserialize, Serialize};
+
 use crate::{
+    command_log::LoggedCommand,
+    config::HistoryMode,
     custom_error::{CustomError, CustomResult},
     logger::LoggerTrait,
+    workers::release_branch::resolve_base,
 };
+
+const FIELD_SEPARATOR: char = '\u{1f}';
+const RECORD_SEPARATOR: char = '\u{1e}';
+const LOG_FORMAT: &str =
+    "--format=%H%x1f%P%x1f%an%x1f%ae%x1f%aI%x1f%s%x1f%b%x1f%(trailers:only,unfold)%x1e";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Trailer {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Commit {
+    pub hash: String,
+    pub parents: Vec<String>,
+    pub author: String,
+    pub email: String,
+    pub date: DateTime<FixedOffset>,
+    pub subject: String,
+    pub body: String,
+    pub trailers: Vec<Trailer>,
+}
+
+impl Commit {
+    // Matches the commits made by `Patcher` (`@1.2.3 release`) and `release/1.2.3` subjects.
+    pub fn is_release(&self) -> bool {
+        let subject = self.subject.trim();
+        let is_version_commit = subject
+            .strip_prefix('@')
+            .and_then(|rest| rest.strip_suffix(" release"))
+            .is_some_and(|version| !version.is_empty() && !version.contains(' '));
+        let is_release_subject = subject
+            .strip_prefix("release/")
+            .is_some_and(|version| !version.is_empty() && !version.contains(' '));
+
+        is_version_commit || is_release_subject
+    }
+}
+
+// Patch-id comparison of the source branch against the release branch
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BranchComparison {
+    pub missing: Vec<Commit>,
+    pub cherry_picked: Vec<Commit>,
+    pub release_only: Vec<Commit>,
+}
+
+pub struct History {
+    // Revision range the commits were collected from
+    pub range: String,
+    pub commits: Vec<Commit>,
+    pub comparison: Option<BranchComparison>,
+}
+
 pub struct HistoryProvider<'repo> {
     pub path: &'repo String,
+    pub branch: &'repo String,
+    pub release_branch: &'repo String,
+    // The release branch is created from it later in the run when it is missing
+    pub release_branch_base: Option<&'repo String>,
+    pub first_parent: bool,
+    pub mode: HistoryMode,
+    // Path globs relative to the repo root. When set, only commits touching them are listed.
+    pub include: &'repo [String],
+    pub exclude: &'repo [String],
 }
 
 impl<'config> LoggerTrait for HistoryProvider<'config> {}
 impl<'repo> HistoryProvider<'repo> {
-    pub fn provide(&self) -> CustomResult<String> {
+    pub fn provide(&self) -> CustomResult<History> {
+        match self.mode {
+            HistoryMode::Range => self.provide_range(),
+            HistoryMode::Cherry => self.provide_cherry(),
+        }
+    }
+
+    // Subjects of the merges that brought each commit into the branch, keyed by commit hash.
+    // Branch names only show up there. A merge brought a commit in when its first parent
+    // does not descend from the commit, nested merges are all listed.
+    pub fn get_merge_subjects(
+        &self,
+        commits: &[Commit],
+    ) -> CustomResult<HashMap<String, Vec<String>>> {
+        let logger = self.get_logger();
+        let target = format!("origin/{}", self.branch);
+        let mut merge_subjects = HashMap::new();
+        for commit in commits {
+            let output = Command::new("git")
+                .arg("log")
+                .arg("--ancestry-path")
+                .arg("--format=%H%x1f%P%x1f%s")
+                .arg(format!("{}..{}", commit.hash, target))
+                .current_dir(self.path)
+                .logged_output()
+                .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+            if !output.status.success() {
+                logger.error(format!("Failed to find merges for repo: {}", self.path).as_str());
+                logger
+                    .error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+
+                return Err(CustomError::CommandExecution(
+                    "Failed to find merges for repo".to_string(),
+                ));
+            }
+
+            let subjects = get_merged_by(&commit.hash, &String::from_utf8_lossy(&output.stdout));
+            if !subjects.is_empty() {
+                merge_subjects.insert(commit.hash.clone(), subjects);
+            }
+        }
+
+        Ok(merge_subjects)
+    }
+
+    fn provide_cherry(&self) -> CustomResult<History> {
+        let logger = self.get_logger();
+        logger.info(
+            format!(
+                "Comparing {} with {} for repo: {}",
+                self.branch, self.release_branch, self.path
+            )
+            .as_str(),
+        );
+        let release_ref = self.get_release_ref()?;
+        let range = format!("{}...origin/{}", release_ref, self.branch);
+
+        let branch_commits = parse_git_log(&self.get_git_log(&["--right-only", &range], true)?)?;
+        let missing =
+            parse_git_log(&self.get_git_log(&["--right-only", "--cherry-pick", &range], true)?)?;
+        let release_only =
+            parse_git_log(&self.get_git_log(&["--left-only", "--cherry-pick", &range], true)?)?;
+        let (missing, cherry_picked) = split_cherry_picked(branch_commits, missing);
+        logger.debug(
+            format!(
+                "Already cherry-picked into {}: {:?}. Only in {}: {:?}",
+                self.release_branch,
+                get_subjects_string(&cherry_picked),
+                self.release_branch,
+                get_subjects_string(&release_only)
+            )
+            .as_str(),
+        );
+        logger.info(
+            format!(
+                "Compared branches for repo: {}. Missing: {}, cherry-picked: {}, release only: {}",
+                self.path,
+                missing.len(),
+                cherry_picked.len(),
+                release_only.len()
+            )
+            .as_str(),
+        );
+
+        Ok(History {
+            range: format!("{} (patch-id){}", range, self.get_scope_suffix()),
+            commits: missing.clone(),
+            comparison: Some(BranchComparison {
+                missing,
+                cherry_picked,
+                release_only,
+            }),
+        })
+    }
+
+    fn provide_range(&self) -> CustomResult<History> {
         let logger = self.get_logger();
         logger.info(format!("Calculating difference for repo: {}", self.path).as_str());
-        let history_string = self.get_git_history_as_string()?;
-        logger.info(format!("Calculated difference for repo: {}", self.path).as_str());
+        let target = format!("origin/{}", self.branch);
+        let start = match self.get_last_release_tag(&target)? {
+            Some(tag) => Some(tag),
+            None => self.get_merge_base(&target)?,
+        };
+        logger.info(
+            format!(
+                "Calculated difference for repo: {}. Start: {:?}",
+                self.path, start
+            )
+            .as_str(),
+        );
 
         logger.info(format!("Generating history for repo: {}", self.path).as_str());
-        let result = self.generate_git_history_string(history_string)?;
-        logger.info(format!("Generated history for repo: {}", self.path).as_str());
+        let history = match start {
+            Some(start) => {
+                let range = format!("{}..{}", start, target);
+                let commits = parse_git_log(&self.get_git_log(&[&range], true)?)?;
+                History {
+                    range: format!("{}{}", range, self.get_scope_suffix()),
+                    commits,
+                    comparison: None,
+                }
+            }
+            None => {
+                logger.warn(
+                    format!(
+                        "No release tag or merge-base found for repo: {}. Using commits since the last release commit",
+                        self.path
+                    )
+                    .as_str(),
+                );
+                // Release commits may not touch the scoped paths, so the cut-off is
+                // searched in the whole history and the scope is applied afterwards
+                let commits = parse_git_log(&self.get_git_log(&[&target], false)?)?;
+                let mut commits = get_unreleased_commits(commits);
+                if self.is_scoped() {
+                    let scoped = parse_git_log(&self.get_git_log(&[&target], true)?)?;
+                    commits.retain(|commit| scoped.iter().any(|scoped| scoped.hash == commit.hash));
+                }
+                History {
+                    range: format!(
+                        "{} (until the last release commit){}",
+                        target,
+                        self.get_scope_suffix()
+                    ),
+                    commits,
+                    comparison: None,
+                }
+            }
+        };
+        logger.info(
+            format!(
+                "Generated history for repo: {}. Range: {}, commits: {}",
+                self.path,
+                history.range,
+                history.commits.len()
+            )
+            .as_str(),
+        );
 
-        Ok(result)
+        Ok(history)
     }
 
-    fn generate_git_history_string(&self, history: String) -> CustomResult<String> {
-        let mut target = String::new();
-        let parts = history.split('\n');
+    // A missing release branch has nothing released yet, everything since its base is compared
+    fn get_release_ref(&self) -> CustomResult<String> {
+        let release_ref = format!("origin/{}", self.release_branch);
+        let output = Command::new("git")
+            .arg("rev-parse")
+            .arg("--verify")
+            .arg("-q")
+            .arg(format!("{}^{{commit}}", release_ref))
+            .current_dir(self.path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if output.status.success() {
+            return Ok(release_ref);
+        }
 
-        for part in parts {
-            let trimmed = part.trim().replace("* ", "");
-            if trimmed.starts_with("release/") || trimmed.starts_with('@') {
-                return Ok(target);
+        match self.release_branch_base {
+            Some(base) => {
+                self.get_logger().warn(
+                    format!(
+                        "Release branch {} not found, comparing with its base {} for repo: {}",
+                        release_ref, base, self.path
+                    )
+                    .as_str(),
+                );
+                resolve_base(self.path, base)
             }
+            None => Err(CustomError::CommandExecution(format!(
+                "Release branch {} not found. Set git.release_branch_base to create it",
+                release_ref
+            ))),
+        }
+    }
+
+    fn get_last_release_tag(&self, target: &str) -> CustomResult<Option<String>> {
+        let output = Command::new("git")
+            .arg("describe")
+            .arg("--tags")
+            .arg("--abbrev=0")
+            .arg("--match")
+            .arg("release/*")
+            .arg(target)
+            .current_dir(self.path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            self.get_logger()
+                .debug(format!("No release tag found for repo: {}", self.path).as_str());
+            return Ok(None);
+        }
 
-            target.push_str(&trimmed);
-            target.push('\n');
+        Ok(Some(
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        ))
+    }
+
+    fn get_merge_base(&self, target: &str) -> CustomResult<Option<String>> {
+        let output = Command::new("git")
+            .arg("merge-base")
+            .arg(target)
+            .arg(format!("origin/{}", self.release_branch))
+            .current_dir(self.path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            self.get_logger()
+                .debug(format!("No merge-base found for repo: {}", self.path).as_str());
+            return Ok(None);
         }
 
-        Ok(target)
+        Ok(Some(
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        ))
+    }
+
+    fn is_scoped(&self) -> bool {
+        !self.include.is_empty() || !self.exclude.is_empty()
+    }
+
+    fn get_scope_suffix(&self) -> String {
+        if !self.is_scoped() {
+            return String::new();
+        }
+        format!(
+            " -- {}",
+            get_pathspecs(self.include, self.exclude).join(" ")
+        )
     }
 
-    fn get_git_history_as_string(&self) -> CustomResult<String> {
+    fn get_git_log(&self, args: &[&str], scoped: bool) -> CustomResult<String> {
         let logger = self.get_logger();
         logger.info(format!("Providing history for repo: {}", self.path).as_str());
-        let output = Command::new("git")
-            .arg("log")
-            .arg("--pretty=format:%s")
-            .arg("--graph")
-            .arg("--abbrev-commit")
-            .arg("--decorate")
-            .arg("--date=relative")
-            .arg("-100")
+        let mut command = Command::new("git");
+        command.arg("log").arg(LOG_FORMAT);
+        match self.mode {
+            HistoryMode::Range if self.first_parent => {
+                command.arg("--first-parent");
+            }
+            HistoryMode::Cherry => {
+                command.arg("--no-merges");
+            }
+            _ => {}
+        }
+        command.args(args).arg("--");
+        if scoped {
+            command.args(get_pathspecs(self.include, self.exclude));
+        }
+        let output = command
             .current_dir(self.path)
-            .output()
+            .logged_output()
             .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
         if !output.status.success() {
             logger.error(format!("Failed to provide history for repo: {}", self.path).as_str());
@@ -64,6 +363,297 @@ impl<'repo> HistoryProvider<'repo> {
         }
         logger.info(format!("Provided history for repo: {}", self.path).as_str());
 
-        return Ok(String::from_utf8_lossy(&output.stdout).to_string());
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+}
+
+// Reads `git log --ancestry-path --format=%H%x1f%P%x1f%s <commit>..<target>`, which only
+// lists descendants of `commit`, and returns the subjects of the merges whose first parent
+// is not one of them.
+fn get_merged_by(commit: &str, log: &str) -> Vec<String> {
+    let records: Vec<Vec<&str>> = log
+        .lines()
+        .map(|line| line.splitn(3, FIELD_SEPARATOR).collect::<Vec<&str>>())
+        .filter(|fields| fields.len() == 3)
+        .collect();
+    let mut descendants: HashSet<&str> = records.iter().map(|fields| fields[0]).collect();
+    descendants.insert(commit);
+
+    records
+        .iter()
+        .filter_map(|fields| {
+            let parents: Vec<&str> = fields[1].split_whitespace().collect();
+            let brought_in = parents.len() > 1 && !descendants.contains(parents[0]);
+            brought_in.then(|| fields[2].to_string())
+        })
+        .collect()
+}
+
+// Globs are matched by git itself. `*` does not cross directories, `**` does and
+// a plain directory matches everything below it.
+pub fn get_pathspecs(include: &[String], exclude: &[String]) -> Vec<String> {
+    include
+        .iter()
+        .map(|path| format!(":(glob){}", path))
+        .chain(
+            exclude
+                .iter()
+                .map(|path| format!(":(glob,exclude){}", path)),
+        )
+        .collect()
+}
+
+// Fallback for repos without release tags. Commits are listed newest first, so everything
+// before the first release commit is unreleased.
+fn get_unreleased_commits(commits: Vec<Commit>) -> Vec<Commit> {
+    commits
+        .into_iter()
+        .take_while(|commit| !commit.is_release())
+        .collect()
+}
+
+// Splits the source branch commits into ones that are missing on the release branch and
+// ones that already have a patch-id equivalent there. Version bump commits are never missing.
+fn split_cherry_picked(branch: Vec<Commit>, missing: Vec<Commit>) -> (Vec<Commit>, Vec<Commit>) {
+    let missing_hashes: Vec<&String> = missing.iter().map(|commit| &commit.hash).collect();
+    let cherry_picked = branch
+        .iter()
+        .filter(|commit| !missing_hashes.contains(&&commit.hash))
+        .cloned()
+        .collect();
+    let missing = missing
+        .into_iter()
+        .filter(|commit| !commit.is_release())
+        .collect();
+
+    (missing, cherry_picked)
+}
+
+pub fn parse_git_log(log: &str) -> CustomResult<Vec<Commit>> {
+    log.split(RECORD_SEPARATOR)
+        .map(|record| record.trim_start_matches('\n'))
+        .filter(|record| !record.trim().is_empty())
+        .map(parse_commit)
+        .collect()
+}
+
+fn parse_commit(record: &str) -> CustomResult<Commit> {
+    let fields: Vec<&str> = record.split(FIELD_SEPARATOR).collect();
+    if fields.len() != 8 {
+        return Err(CustomError::CommandExecution(format!(
+            "Unexpected git log record: {:?}",
+            record
+        )));
+    }
+
+    let date = DateTime::parse_from_rfc3339(fields[4])
+        .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+    let trailers = fields[7]
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| Trailer {
+            key: key.trim().to_string(),
+            value: value.trim().to_string(),
+        })
+        .collect();
+
+    Ok(Commit {
+        hash: fields[0].to_string(),
+        parents: fields[1].split_whitespace().map(String::from).collect(),
+        author: fields[2].to_string(),
+        email: fields[3].to_string(),
+        date,
+        subject: fields[5].to_string(),
+        body: fields[6].trim().to_string(),
+        trailers,
+    })
+}
+
+pub fn get_subjects_string(commits: &[Commit]) -> String {
+    let mut result = String::new();
+    for commit in commits {
+        result.push_str(&commit.subject);
+        result.push('\n');
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{commit_file, git, init_git_repo, TempDir};
+
+    fn get_record(hash: &str, parents: &str, subject: &str, body: &str, trailers: &str) -> String {
+        format!(
+            "{hash}\u{1f}{parents}\u{1f}Jane Doe\u{1f}jane@example.com\u{1f}2024-01-02T10:00:00+02:00\u{1f}{subject}\u{1f}{body}\u{1f}{trailers}\u{1e}\n"
+        )
+    }
+
+    #[test]
+    fn test_parse_git_log_reads_all_fields() {
+        let log = get_record(
+            "abc123",
+            "p1 p2",
+            "* feat: support @mentions",
+            "Some body\n\nCo-authored-by: John <john@example.com>\n",
+            "Co-authored-by: John <john@example.com>\n",
+        );
+
+        let commits = parse_git_log(&log).unwrap();
+
+        assert_eq!(commits.len(), 1);
+        let commit = &commits[0];
+        assert_eq!(commit.hash, "abc123");
+        assert_eq!(commit.parents, vec!["p1", "p2"]);
+        assert_eq!(commit.author, "Jane Doe");
+        assert_eq!(commit.email, "jane@example.com");
+        assert_eq!(commit.date.to_rfc3339(), "2024-01-02T10:00:00+02:00");
+        assert_eq!(commit.subject, "* feat: support @mentions");
+        assert_eq!(
+            commit.body,
+            "Some body\n\nCo-authored-by: John <john@example.com>"
+        );
+        assert_eq!(
+            commit.trailers,
+            vec![Trailer {
+                key: String::from("Co-authored-by"),
+                value: String::from("John <john@example.com>"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_unreleased_commits_stop_at_release_commit() {
+        let log = [
+            get_record("c3", "c2", "@mention handling", "", ""),
+            get_record("c2", "c1", "release/notes update", "", ""),
+            get_record("c1", "c0", "@5.9.25a release", "", ""),
+            get_record("c0", "", "initial", "", ""),
+        ]
+        .concat();
+
+        let commits = get_unreleased_commits(parse_git_log(&log).unwrap());
+
+        let hashes: Vec<&str> = commits.iter().map(|commit| commit.hash.as_str()).collect();
+        assert_eq!(hashes, vec!["c3", "c2"]);
+    }
+
+    #[test]
+    fn test_split_cherry_picked_separates_equivalent_commits() {
+        let branch = [
+            get_record("c3", "c2", "@1.0.1 release", "", ""),
+            get_record("c2", "c1", "fix: second", "", ""),
+            get_record("c1", "c0", "feat: first", "", ""),
+        ]
+        .concat();
+        let missing = [
+            get_record("c3", "c2", "@1.0.1 release", "", ""),
+            get_record("c2", "c1", "fix: second", "", ""),
+        ]
+        .concat();
+
+        let (missing, cherry_picked) = split_cherry_picked(
+            parse_git_log(&branch).unwrap(),
+            parse_git_log(&missing).unwrap(),
+        );
+
+        let missing: Vec<&str> = missing.iter().map(|commit| commit.hash.as_str()).collect();
+        let cherry_picked: Vec<&str> = cherry_picked
+            .iter()
+            .map(|commit| commit.hash.as_str())
+            .collect();
+        assert_eq!(missing, vec!["c2"]);
+        assert_eq!(cherry_picked, vec!["c1"]);
+    }
+
+    #[test]
+    fn test_get_pathspecs_marks_excludes() {
+        let include = vec![String::from("packages/api")];
+        let exclude = vec![String::from("**/*.md")];
+
+        assert_eq!(
+            get_pathspecs(&include, &exclude),
+            vec![":(glob)packages/api", ":(glob,exclude)**/*.md"]
+        );
+    }
+
+    #[test]
+    fn test_parse_git_log_fails_on_malformed_record() {
+        let result = parse_git_log("not a record\u{1e}");
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_get_merged_by_skips_later_merges() {
+        let log = "m2\u{1f}m1 b2\u{1f}Merge branch 'feature/CONF-13'\n\
+            m1\u{1f}a0 c1\u{1f}Merge branch 'feature/CONF-12'\n";
+
+        assert_eq!(
+            get_merged_by("c1", log),
+            vec!["Merge branch 'feature/CONF-12'"]
+        );
+        assert!(get_merged_by("m2", "").is_empty());
+    }
+
+    #[test]
+    fn test_get_merge_subjects_reads_branch_merges() {
+        let dir = TempDir::new("history_merge_subjects");
+        let work = init_git_repo(&dir);
+        git(&work, &["push", "-q", "origin", "main:release"]);
+        git(&work, &["checkout", "-q", "-b", "feature/CONF-12-export"]);
+        let feature = commit_file(&work, "export.txt", "export\n", "feat: export");
+        git(&work, &["checkout", "-q", "main"]);
+        git(
+            &work,
+            &[
+                "merge",
+                "-q",
+                "--no-ff",
+                "--no-edit",
+                "feature/CONF-12-export",
+            ],
+        );
+        git(&work, &["checkout", "-q", "-b", "feature/CONF-13-import"]);
+        let other = commit_file(&work, "import.txt", "import\n", "feat: import");
+        git(&work, &["checkout", "-q", "main"]);
+        git(
+            &work,
+            &[
+                "merge",
+                "-q",
+                "--no-ff",
+                "--no-edit",
+                "feature/CONF-13-import",
+            ],
+        );
+        git(&work, &["push", "-q", "origin", "main"]);
+        git(&work, &["fetch", "-q", "origin"]);
+
+        let path = work.to_string_lossy().to_string();
+        let names = [String::from("main"), String::from("release")];
+        let provider = HistoryProvider {
+            path: &path,
+            branch: &names[0],
+            release_branch: &names[1],
+            release_branch_base: None,
+            first_parent: false,
+            mode: HistoryMode::Cherry,
+            include: &[],
+            exclude: &[],
+        };
+        let history = provider.provide().unwrap();
+        assert_eq!(history.commits.len(), 2);
+
+        let merge_subjects = provider.get_merge_subjects(&history.commits).unwrap();
+        assert_eq!(
+            merge_subjects[&feature],
+            vec!["Merge branch 'feature/CONF-12-export'"]
+        );
+        assert_eq!(
+            merge_subjects[&other],
+            vec!["Merge branch 'feature/CONF-13-import'"]
+        );
     }
 }
@@ -0,0 +1,213 @@
+use std::process::Command;
+
+use crate::{
+    command_log::LoggedCommand,
+    config::HooksConfig,
+    custom_error::{CustomError, CustomResult},
+    logger::LoggerTrait,
+};
+
+const ENV_PREFIX: &str = "VERSION_UPDATER_";
+
+#[derive(Debug, Clone, Copy)]
+pub enum Hook {
+    PreCheckout,
+    PostCheckout,
+    PreRebuild,
+    PostRebuild,
+    PrePatch,
+    PostPatch,
+    PrePush,
+    PostPush,
+    PrePr,
+    PostPr,
+}
+
+impl Hook {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Hook::PreCheckout => "pre_checkout",
+            Hook::PostCheckout => "post_checkout",
+            Hook::PreRebuild => "pre_rebuild",
+            Hook::PostRebuild => "post_rebuild",
+            Hook::PrePatch => "pre_patch",
+            Hook::PostPatch => "post_patch",
+            Hook::PrePush => "pre_push",
+            Hook::PostPush => "post_push",
+            Hook::PrePr => "pre_pr",
+            Hook::PostPr => "post_pr",
+        }
+    }
+
+    fn get_commands<'config>(&self, config: &'config HooksConfig) -> &'config [String] {
+        match self {
+            Hook::PreCheckout => &config.pre_checkout,
+            Hook::PostCheckout => &config.post_checkout,
+            Hook::PreRebuild => &config.pre_rebuild,
+            Hook::PostRebuild => &config.post_rebuild,
+            Hook::PrePatch => &config.pre_patch,
+            Hook::PostPatch => &config.post_patch,
+            Hook::PrePush => &config.pre_push,
+            Hook::PostPush => &config.post_push,
+            Hook::PrePr => &config.pre_pr,
+            Hook::PostPr => &config.post_pr,
+        }
+    }
+}
+
+// What is known about the repo when the hook runs
+#[derive(Debug, Clone, Copy)]
+pub struct HookContext<'repo> {
+    pub path: &'repo str,
+    pub current_version: Option<&'repo str>,
+    pub next_version: Option<&'repo str>,
+    pub pr_link: Option<&'repo str>,
+}
+
+impl<'repo> HookContext<'repo> {
+    pub fn new(path: &'repo str) -> Self {
+        Self {
+            path,
+            current_version: None,
+            next_version: None,
+            pr_link: None,
+        }
+    }
+}
+
+// Runs the global hooks of a stage and then the ones of the repo.
+// The first failing command stops the repo.
+pub struct HookRunner<'repo> {
+    pub repo_name: &'repo String,
+    pub branch: &'repo String,
+    pub release_branch: &'repo String,
+    pub global: &'repo HooksConfig,
+    pub repo: Option<&'repo HooksConfig>,
+}
+
+impl LoggerTrait for HookRunner<'_> {}
+impl HookRunner<'_> {
+    pub fn run(&self, hook: Hook, context: HookContext) -> CustomResult<()> {
+        let repo_commands = self
+            .repo
+            .map(|repo| hook.get_commands(repo))
+            .unwrap_or_default();
+        for script in hook.get_commands(self.global).iter().chain(repo_commands) {
+            self.run_command(hook, context, script)?;
+        }
+
+        Ok(())
+    }
+
+    fn run_command(&self, hook: Hook, context: HookContext, script: &str) -> CustomResult<()> {
+        let logger = self.get_logger();
+        logger.info(
+            format!(
+                "Running {} hook in repo: {}. {}",
+                hook.get_name(),
+                context.path,
+                script
+            )
+            .as_str(),
+        );
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script).current_dir(context.path);
+        for (key, value) in self.get_env(hook, context) {
+            command.env(format!("{}{}", ENV_PREFIX, key), value);
+        }
+
+        let output = command
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            logger.error(
+                format!(
+                    "Hook {} failed in repo: {}. {}",
+                    hook.get_name(),
+                    context.path,
+                    script
+                )
+                .as_str(),
+            );
+            logger.error(format!("Output: {}", String::from_utf8_lossy(&output.stdout)).as_str());
+            logger.error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+
+            return Err(CustomError::CommandExecution(format!(
+                "Hook {} failed with {}: {}",
+                hook.get_name(),
+                output.status,
+                script
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn get_env<'env>(
+        &'env self,
+        hook: Hook,
+        context: HookContext<'env>,
+    ) -> Vec<(&'static str, &'env str)> {
+        let mut env = vec![
+            ("HOOK", hook.get_name()),
+            ("REPO_NAME", self.repo_name.as_str()),
+            ("REPO_PATH", context.path),
+            ("BRANCH", self.branch.as_str()),
+            ("RELEASE_BRANCH", self.release_branch.as_str()),
+        ];
+        for (key, value) in [
+            ("CURRENT_VERSION", context.current_version),
+            ("NEXT_VERSION", context.next_version),
+            ("PR_URL", context.pr_link),
+        ] {
+            if let Some(value) = value {
+                env.push((key, value));
+            }
+        }
+
+        env
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_passes_context_and_stops_on_failure() {
+        let path = std::env::temp_dir().to_string_lossy().to_string();
+        let name = String::from("api");
+        let branch = String::from("dev");
+        let release_branch = String::from("master");
+        let global = HooksConfig {
+            pre_patch: vec![
+                r#"test "$VERSION_UPDATER_NEXT_VERSION" = "1.0.1""#.to_string(),
+                r#"test "$VERSION_UPDATER_HOOK" = "pre_patch""#.to_string(),
+            ],
+            ..Default::default()
+        };
+        let repo = HooksConfig {
+            pre_patch: vec![r#"test -n "$VERSION_UPDATER_PR_URL""#.to_string()],
+            ..Default::default()
+        };
+        let runner = HookRunner {
+            repo_name: &name,
+            branch: &branch,
+            release_branch: &release_branch,
+            global: &global,
+            repo: Some(&repo),
+        };
+        let context = HookContext {
+            path: &path,
+            current_version: Some("1.0.0"),
+            next_version: Some("1.0.1"),
+            pr_link: None,
+        };
+
+        assert!(runner.run(Hook::PostPatch, context).is_ok());
+        assert!(matches!(
+            runner.run(Hook::PrePatch, context),
+            Err(CustomError::CommandExecution(error)) if error.contains("PR_URL")
+        ));
+    }
+}
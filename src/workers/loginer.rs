@@ -1,4 +1,5 @@
 use crate::{
+    command_log::LoggedCommand,
     custom_error::{CustomError, CustomResult},
     logger::Logger,
 };
@@ -16,10 +17,12 @@ enum TargetConfig {
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct SsoCacheEntry {
-    startUrl: Option<String>,
-    expiresAt: Option<DateTime<Utc>>,
-    _accessToken: Option<String>,
+    start_url: Option<String>,
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(rename = "accessToken")]
+    _access_token: Option<String>,
 }
 
 pub fn login(branch: &str, script_path: &str, role: &str, sso_start_url: &str) -> CustomResult<()> {
@@ -57,7 +60,7 @@ fn use_target_config(target_config: &String) -> CustomResult<()> {
     logger.debug(format!("Using target config: {:?}", target_config).as_str());
     let output = Command::new("npmrc")
         .arg(target_config)
-        .output()
+        .timed_output()
         .map_err(|err| CustomError::NpmConfigError(err.to_string()))?;
     if !output.status.success() {
         logger.error("Failed to use target config");
@@ -90,7 +93,7 @@ pub fn generate_aws_tokens(sso_start_url: &str) -> CustomResult<()> {
             .arg("login")
             .arg("--sso-session")
             .arg("sso")
-            .output()
+            .timed_output()
             .map_err(|err| CustomError::NpmConfigError(err.to_string()))?;
         if !output.status.success() {
             logger.error("Failed to login to AWS");
@@ -126,7 +129,7 @@ fn generate_npm_token() -> CustomResult<()> {
         .arg("conform5-npm-common")
         .arg("--profile")
         .arg("conform5-edetek-dev-01.conform5-batch-dev")
-        .output()
+        .timed_output()
         .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
     if !output.status.success() {
         logger.error("Failed to generate token to codeartifact");
@@ -147,7 +150,7 @@ pub fn switch_role(script_path: &str, role: &str) -> CustomResult<()> {
     let output = Command::new("zsh")
         .arg("-c")
         .arg(&command_string)
-        .output()
+        .timed_output()
         .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
     if !output.status.success() {
         logger.error("Failed to switch the aws role");
@@ -205,7 +208,8 @@ fn sso_token_still_valid(sso_start_url: &str) -> CustomResult<bool> {
             }
         };
 
-        if let (Some(start_url), Some(expires_at)) = (cache_entry.startUrl, cache_entry.expiresAt) {
+        if let (Some(start_url), Some(expires_at)) = (cache_entry.start_url, cache_entry.expires_at)
+        {
             if start_url == sso_start_url && expires_at > Utc::now() {
                 return Ok(true);
             }
@@ -1,6 +1,23 @@
+pub mod back_merge;
 pub mod branch;
+pub mod build_cache;
+pub mod changelog;
+pub mod cleaner;
+pub mod cloner;
+pub mod dependencies;
+pub mod gates;
 pub mod history;
+pub mod hooks;
 pub mod loginer;
 pub mod patcher;
+pub mod preflight;
+pub mod python;
 pub mod rebuilder;
+pub mod release_branch;
+pub mod release_notes;
+pub mod release_rules;
+pub mod rollback;
+pub mod tickets;
+pub mod verifier;
 pub mod version;
+pub mod worktree;
@@ -1,7 +1,14 @@
-use crate::config::RepoType;
+use crate::command_log::{self, LoggedCommand};
+use crate::config::{ChangelogConfig, RepoType};
 use crate::custom_error::{CustomError, CustomResult};
 use crate::logger::LoggerTrait;
+use crate::workers::changelog::ChangelogWriter;
+use crate::workers::history::Commit;
+use crate::workers::hooks::{Hook, HookContext, HookRunner};
 use crate::workers::loginer::get_switch_role_command;
+use crate::workers::rebuilder::PackageManager;
+use crate::workers::verifier::{PackageInfo, PackageVerifier};
+use regex::Regex;
 use serde_derive::{Deserialize, Serialize};
 use std::fs::{read_to_string, write};
 use std::path::Path;
@@ -9,13 +16,56 @@ use std::process::{Command, Output};
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct Commit {
-    pull_request: PullRequest,
+pub struct CreatePullRequestOutput {
+    pub pull_request: PullRequest,
 }
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct PullRequest {
-    pull_request_id: String,
+pub struct PullRequest {
+    pub pull_request_id: String,
+}
+
+pub fn get_pr_link(repo_name: &str, pull_request_id: &str) -> String {
+    format!(
+        "https://console.aws.amazon.com/codesuite/codecommit/repositories/{}/pull-requests/{}/details?region=us-east-1",
+        repo_name,
+        pull_request_id
+    )
+}
+
+// CodeCommit rejects descriptions longer than this
+const PR_DESCRIPTION_LIMIT: usize = 10240;
+
+fn get_pr_description(release_notes: &str) -> String {
+    let description = release_notes.trim();
+    if description.len() <= PR_DESCRIPTION_LIMIT {
+        return description.to_string();
+    }
+
+    let suffix = "\n...";
+    let mut end = PR_DESCRIPTION_LIMIT - suffix.len();
+    while !description.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &description[..end], suffix)
+}
+
+// Single-quotes a value for the shell the PR command runs in
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+// Filled in as the release is published, so a failed patch still reports
+// what has to be rolled back
+#[derive(Default)]
+pub struct PatchResult {
+    pub commit: Option<String>,
+    pub tag: Option<String>,
+    pub package: Option<PackageInfo>,
+    pub pr_id: Option<String>,
+    pub pr_link: Option<String>,
+    // A failed post_push or post_pr hook, the release itself is published
+    pub hook_error: Option<String>,
 }
 
 pub struct Patcher<'repo> {
@@ -29,27 +79,72 @@ pub struct Patcher<'repo> {
     pub role: &'repo String,
     pub sso_script_path: &'repo String,
     pub disable_checks: bool,
+    pub history: &'repo [Commit],
+    pub notes: &'repo [String],
+    // Used as the PR description
+    pub release_notes: &'repo str,
+    pub changelog: &'repo Option<ChangelogConfig>,
+    pub hooks: &'repo HookRunner<'repo>,
+    // Checks the package before anything is pushed, a failure takes the release commit back.
+    // The package is stored as the baseline of the next run once it is pushed.
+    pub verifier: Option<&'repo PackageVerifier<'repo>>,
 }
 
 impl<'config> LoggerTrait for Patcher<'config> {}
 
 impl<'repo> Patcher<'repo> {
-    pub fn update_version_in_repo(&self) -> CustomResult<String> {
+    pub fn update_version_in_repo(&self, result: &mut PatchResult) -> CustomResult<()> {
         let logger = self.get_logger();
         logger.info(format!("Updating version in repo: {}", self.path).as_str());
 
+        self.hooks
+            .run(Hook::PrePatch, self.get_hook_context(None))?;
         match self.repo_type {
             RepoType::Node => self.up_node_version()?,
             RepoType::Python => self.up_python_version()?,
         }
 
+        if let Some(changelog) = self.changelog {
+            let writer = ChangelogWriter {
+                path: self.path,
+                version: &self.next_version,
+                history: self.history,
+                notes: self.notes,
+                config: changelog,
+            };
+            writer.write()?;
+        }
+
         self.add_changes()?;
         self.commit_changes()?;
-        self.add_tags()?;
+        // Nothing is pushed yet, a failure up to the push takes the release commit back
+        let commit = match self.prepare_release_commit(result) {
+            Ok(commit) => commit,
+            Err(e) => {
+                self.undo_release_commit();
+                result.tag = None;
+                return Err(e);
+            }
+        };
+
         self.push_to_origin()?;
+        result.commit = Some(commit);
         self.push_to_tags()?;
+        if let (Some(verifier), Some(package)) = (self.verifier, &result.package) {
+            if let Err(e) = verifier.store_package(package) {
+                logger.warn(
+                    format!("Failed to store package of repo: {}. {}", self.path, e).as_str(),
+                );
+            }
+        }
+        self.run_published_hook(Hook::PostPush, None, result);
 
-        let pr_link = self.create_pr()?;
+        command_log::set_stage(Some(self.repo_name), "pr");
+        self.hooks.run(Hook::PrePr, self.get_hook_context(None))?;
+        let (pr_id, pr_link) = self.create_pr()?;
+        result.pr_id = Some(pr_id);
+        result.pr_link = Some(pr_link.clone());
+        self.run_published_hook(Hook::PostPr, Some(&pr_link), result);
 
         logger.info(
             format!(
@@ -58,7 +153,89 @@ impl<'repo> Patcher<'repo> {
             )
             .as_str(),
         );
-        Ok(pr_link)
+        Ok(())
+    }
+
+    // Tags the release commit and checks it. Returns the release commit.
+    // The tag is recorded as soon as it exists, a rollback deletes it even if it was never pushed.
+    fn prepare_release_commit(&self, result: &mut PatchResult) -> CustomResult<String> {
+        let commit = self.get_head_commit()?;
+        self.add_tags()?;
+        result.tag = Some(format!("release/{}", self.next_version));
+        self.hooks
+            .run(Hook::PostPatch, self.get_hook_context(None))?;
+        if let Some(verifier) = self.verifier {
+            command_log::set_stage(Some(self.repo_name), "verify");
+            result.package = Some(verifier.verify(&self.next_version)?);
+        }
+        command_log::set_stage(Some(self.repo_name), "push");
+        self.hooks.run(Hook::PrePush, self.get_hook_context(None))?;
+
+        Ok(commit)
+    }
+
+    // Removes the local release tag and commit, the repo is left as it was before the patch
+    fn undo_release_commit(&self) {
+        let logger = self.get_logger();
+        logger.warn(format!("Undoing release commit in repo: {}", self.path).as_str());
+        let tag = format!("release/{}", self.next_version);
+        let steps: [&[&str]; 2] = [&["tag", "-d", &tag], &["reset", "--hard", "HEAD^"]];
+        for args in steps {
+            let output = Command::new("git")
+                .args(args)
+                .current_dir(self.path)
+                .logged_output();
+            match output {
+                Ok(output) if output.status.success() => {}
+                // The tag is missing when tagging was what failed
+                Ok(output) => logger.warn(
+                    format!(
+                        "Failed to run git {} in repo: {}. {}",
+                        args.join(" "),
+                        self.path,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    )
+                    .as_str(),
+                ),
+                Err(e) => logger.error(
+                    format!(
+                        "Failed to run git {} in repo: {}. {}",
+                        args.join(" "),
+                        self.path,
+                        e
+                    )
+                    .as_str(),
+                ),
+            }
+        }
+    }
+
+    // Hooks after something was published don't fail the repo, the release is out
+    fn run_published_hook(&self, hook: Hook, pr_link: Option<&str>, result: &mut PatchResult) {
+        if let Err(e) = self.hooks.run(hook, self.get_hook_context(pr_link)) {
+            self.get_logger().error(
+                format!(
+                    "{} hook failed for repo: {}. {}",
+                    hook.get_name(),
+                    self.path,
+                    e
+                )
+                .as_str(),
+            );
+            result.hook_error = Some(e.to_string());
+        }
+    }
+
+    fn get_hook_context<'context>(
+        &'context self,
+        pr_link: Option<&'context str>,
+    ) -> HookContext<'context> {
+        HookContext {
+            path: self.path,
+            current_version: Some(&self.current_version),
+            next_version: Some(&self.next_version),
+            pr_link,
+        }
     }
 
     fn up_node_version(&self) -> CustomResult<()> {
@@ -67,9 +244,23 @@ impl<'repo> Patcher<'repo> {
         self.up_version_by_replacement("package.json", 1)?;
         logger.info(format!("Updated version in package.json: {}", self.path).as_str());
 
-        logger.info(format!("Updating version in package-lock.json: {}", self.path).as_str());
-        self.up_version_by_replacement("package-lock.json", 2)?;
-        logger.info(format!("Updating version in package-lock.json: {}", self.path).as_str());
+        // Only npm keeps the version of the repo's own package in its lockfile
+        let package_manager = PackageManager::detect(Path::new(self.path));
+        let lockfile = package_manager.get_lockfile();
+        if package_manager == PackageManager::Npm && Path::new(self.path).join(lockfile).exists() {
+            logger.info(format!("Updating version in {}: {}", lockfile, self.path).as_str());
+            self.up_version_by_replacement(lockfile, 2)?;
+            logger.info(format!("Updated version in {}: {}", lockfile, self.path).as_str());
+        } else {
+            logger.debug(
+                format!(
+                    "No version to update in the {} lockfile: {}",
+                    package_manager.get_program(),
+                    self.path
+                )
+                .as_str(),
+            );
+        }
 
         logger.info(format!("Patched node repo by replacement: {}", self.path).as_str());
 
@@ -86,25 +277,84 @@ impl<'repo> Patcher<'repo> {
         self.up_version_by_replacement("version.json", 1)?;
         logger.info(format!("Updating version in version.json: {}", self.path).as_str());
 
+        self.up_pyproject_version()?;
+
         logger.info(format!("Patched node repo by replacement: {}", self.path).as_str());
 
         Ok(())
     }
 
+    // The wheel takes a static `version` of pyproject.toml over version.json, so it is
+    // bumped as well. A dynamic version is left to the build backend.
+    fn up_pyproject_version(&self) -> CustomResult<()> {
+        let logger = self.get_logger();
+        let path = Path::new(self.path).join("pyproject.toml");
+        let content = match read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(CustomError::FileSystem(err.to_string())),
+        };
+        let manifest: toml::Value = toml::from_str(&content).map_err(|err| {
+            CustomError::VersionBuild(format!("Could not parse {}: {}", path.display(), err))
+        })?;
+        let keys: [&[&str]; 2] = [&["project", "version"], &["tool", "poetry", "version"]];
+        let versions: Vec<&str> = keys
+            .iter()
+            .filter_map(|keys| {
+                keys.iter()
+                    .try_fold(&manifest, |value, key| value.get(key))
+                    .and_then(|version| version.as_str())
+            })
+            .collect();
+        if versions.is_empty() {
+            logger.debug(format!("No static version in pyproject.toml: {}", self.path).as_str());
+            return Ok(());
+        }
+        if let Some(version) = versions
+            .iter()
+            .find(|version| **version != self.current_version)
+        {
+            return Err(CustomError::VersionBuild(format!(
+                "pyproject.toml version {} does not match the current version {}",
+                version, self.current_version
+            )));
+        }
+
+        logger.info(format!("Updating version in pyproject.toml: {}", self.path).as_str());
+        let pattern = Regex::new(&format!(
+            r#"(?m)^(\s*version\s*=\s*)(["']){}(["'])"#,
+            regex::escape(&self.current_version)
+        ))
+        .map_err(|err| CustomError::VersionBuild(err.to_string()))?;
+        let replacement = format!("${{1}}${{2}}{}${{3}}", self.next_version);
+        let content = pattern.replacen(&content, versions.len(), replacement.as_str());
+        write(&path, content.as_ref()).map_err(|err| CustomError::FileSystem(err.to_string()))?;
+        logger.info(format!("Updated version in pyproject.toml: {}", self.path).as_str());
+
+        Ok(())
+    }
+
     fn up_version_by_replacement(&self, file: &str, replacement_number: usize) -> CustomResult<()> {
         let path = match Path::new(&self.path).join(file).to_str() {
             Some(val) => String::from(val),
             None => return Err(CustomError::VersionBuild("Can't build path".to_string())),
         };
-        let mut package_json_content = read_to_string(&path).expect("Failed to read files");
-
-        package_json_content = package_json_content.replacen(
-            format!("\"version\": \"{}\"", &self.current_version).as_str(),
+        let content = read_to_string(&path)
+            .map_err(|err| CustomError::FileSystem(format!("Failed to read {}: {}", path, err)))?;
+
+        let current = format!("\"version\": \"{}\"", &self.current_version);
+        if !content.contains(&current) {
+            return Err(CustomError::VersionBuild(format!(
+                "Version {} not found in {}",
+                self.current_version, path
+            )));
+        }
+        let content = content.replacen(
+            current.as_str(),
             format!("\"version\": \"{}\"", &self.next_version).as_str(),
             replacement_number,
         );
-        write(&path, package_json_content)
-            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        write(&path, content).map_err(|err| CustomError::FileSystem(err.to_string()))?;
 
         Ok(())
     }
@@ -116,7 +366,7 @@ impl<'repo> Patcher<'repo> {
             .arg("add")
             .arg("--all")
             .current_dir(self.path)
-            .output()
+            .logged_output()
             .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
         if !output.status.success() {
             logger.error(format!("Failed add changes for repo: {}", self.path).as_str());
@@ -146,7 +396,7 @@ impl<'repo> Patcher<'repo> {
         let output = command
             .arg(format!("@{} release", self.next_version))
             .current_dir(self.path)
-            .output()
+            .logged_output()
             .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
         if !output.status.success() {
             logger.error(format!("Failed to commit for repo: {}", self.path).as_str());
@@ -161,6 +411,26 @@ impl<'repo> Patcher<'repo> {
         Ok(())
     }
 
+    fn get_head_commit(&self) -> CustomResult<String> {
+        let logger = self.get_logger();
+        let output = Command::new("git")
+            .arg("rev-parse")
+            .arg("HEAD")
+            .current_dir(self.path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            logger.error(format!("Failed to get release commit for repo: {}", self.path).as_str());
+            logger.error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+
+            return Err(CustomError::CommandExecution(
+                "Failed to get release commit for repo".to_string(),
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
     fn add_tags(&self) -> CustomResult<()> {
         let logger = self.get_logger();
         logger.info(format!("Adding tags to git: {}", self.path).as_str());
@@ -171,7 +441,7 @@ impl<'repo> Patcher<'repo> {
             .arg("-m")
             .arg(format!("release/{} version", self.next_version))
             .current_dir(self.path)
-            .output()
+            .logged_output()
             .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
         if !output.status.success() {
             logger.error(format!("Failed add tags for repo: {}", self.path).as_str());
@@ -197,9 +467,9 @@ impl<'repo> Patcher<'repo> {
         };
 
         let output = command
-            .arg(self.branch.as_str())
+            .arg(format!("HEAD:{}", self.branch))
             .current_dir(self.path)
-            .output()
+            .logged_output()
             .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
         if !output.status.success() {
             logger.error(format!("Failed to push changes for repo: {}", self.path).as_str());
@@ -226,7 +496,7 @@ impl<'repo> Patcher<'repo> {
 
         let output = command
             .current_dir(self.path)
-            .output()
+            .logged_output()
             .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
         if !output.status.success() {
             logger.error(format!("Failed to push tags for repo: {}", self.path).as_str());
@@ -248,34 +518,35 @@ impl<'repo> Patcher<'repo> {
         Ok(())
     }
 
-    fn create_pr(&self) -> CustomResult<String> {
+    fn create_pr(&self) -> CustomResult<(String, String)> {
         let logger = self.get_logger();
         logger.info(format!("Creating PR in AWS: {}", self.path).as_str());
 
         let output = self.execute_pr_create_with_login_command()?;
 
         let str_json = String::from_utf8(output.stdout).expect("Failed to parse stdout");
-        let commit: Commit = serde_json::from_str(&str_json).expect("Failed to parse json");
+        let commit: CreatePullRequestOutput =
+            serde_json::from_str(&str_json).expect("Failed to parse json");
 
-        let pr_link = format!(
-            "https://console.aws.amazon.com/codesuite/codecommit/repositories/{}/pull-requests/{}/details?region=us-east-1",
-            self.repo_name,
-            commit.pull_request.pull_request_id
-        );
+        let pr_link = get_pr_link(self.repo_name, &commit.pull_request.pull_request_id);
         logger.warn(format!("Created PR in AWS: {}, PR: {}", self.path, pr_link).as_str());
 
-        Ok(pr_link)
+        Ok((commit.pull_request.pull_request_id, pr_link))
     }
 
     fn get_pr_create_command_string(&self) -> CustomResult<String> {
         let cda_artifact = format!("'CDA Artifact {}'", self.next_version);
-        let command = format!(
+        let mut command = format!(
             "aws codecommit create-pull-request --title {0} --targets repositoryName={1},sourceReference={2},destinationReference={3}",
             cda_artifact,
             self.repo_name,
             self.branch,
             self.release_branch
         );
+        let description = get_pr_description(self.release_notes);
+        if !description.is_empty() {
+            command.push_str(&format!(" --description {}", quote(&description)));
+        }
 
         Ok(command)
     }
@@ -298,7 +569,7 @@ impl<'repo> Patcher<'repo> {
             .arg("-c")
             .arg(&command_string)
             .current_dir(self.path)
-            .output()
+            .logged_output()
             .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
 
         if !output.status.success() {
@@ -313,3 +584,235 @@ impl<'repo> Patcher<'repo> {
         Ok(output)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::config::HooksConfig;
+    use crate::test_utils::{commit_file, git, init_git_repo, TempDir};
+
+    const PACKAGE_JSON: &str = "{\n  \"name\": \"shared\",\n  \"version\": \"1.0.0\"\n}\n";
+
+    fn with_patcher(path: &String, test: impl FnOnce(&Patcher)) {
+        with_hooks_patcher(path, &HooksConfig::default(), test);
+    }
+
+    fn with_hooks_patcher(path: &String, hooks_config: &HooksConfig, test: impl FnOnce(&Patcher)) {
+        let name = String::from("shared");
+        let branch = String::from("main");
+        let release_branch = String::from("release");
+        let hooks = HookRunner {
+            repo_name: &name,
+            branch: &branch,
+            release_branch: &release_branch,
+            global: hooks_config,
+            repo: None,
+        };
+        let patcher = Patcher {
+            next_version: String::from("1.0.1"),
+            current_version: String::from("1.0.0"),
+            repo_name: &name,
+            path,
+            repo_type: RepoType::Node,
+            branch: &branch,
+            release_branch: &release_branch,
+            role: &name,
+            sso_script_path: &name,
+            disable_checks: true,
+            history: &[],
+            notes: &[],
+            release_notes: "",
+            changelog: &None,
+            hooks: &hooks,
+            verifier: None,
+        };
+
+        test(&patcher);
+    }
+
+    #[test]
+    fn test_up_node_version_updates_npm_lockfile() {
+        let dir = TempDir::new("patcher_npm");
+        let lockfile = "{\n  \"name\": \"shared\",\n  \"version\": \"1.0.0\",\n  \"packages\": {\n    \"\": {\n      \"version\": \"1.0.0\"\n    }\n  }\n}\n";
+        fs::write(dir.join("package.json"), PACKAGE_JSON).unwrap();
+        fs::write(dir.join("package-lock.json"), lockfile).unwrap();
+
+        with_patcher(&dir.to_path_string(), |patcher| {
+            patcher.up_node_version().unwrap()
+        });
+
+        assert_eq!(
+            fs::read_to_string(dir.join("package.json")).unwrap(),
+            PACKAGE_JSON.replace("1.0.0", "1.0.1")
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("package-lock.json")).unwrap(),
+            lockfile.replace("1.0.0", "1.0.1")
+        );
+    }
+
+    #[test]
+    fn test_up_node_version_leaves_yarn_lockfile() {
+        let dir = TempDir::new("patcher_yarn");
+        let lockfile = "shared-utils@^1.0.0:\n  version \"1.0.0\"\n";
+        fs::write(dir.join("package.json"), PACKAGE_JSON).unwrap();
+        fs::write(dir.join("yarn.lock"), lockfile).unwrap();
+
+        with_patcher(&dir.to_path_string(), |patcher| {
+            patcher.up_node_version().unwrap()
+        });
+
+        assert_eq!(
+            fs::read_to_string(dir.join("package.json")).unwrap(),
+            PACKAGE_JSON.replace("1.0.0", "1.0.1")
+        );
+        assert_eq!(fs::read_to_string(dir.join("yarn.lock")).unwrap(), lockfile);
+    }
+
+    #[test]
+    fn test_up_node_version_leaves_pnpm_lockfile() {
+        let dir = TempDir::new("patcher_pnpm");
+        let lockfile =
+            "lockfileVersion: '6.0'\ndependencies:\n  shared-utils:\n    version: 1.0.0\n";
+        fs::write(dir.join("package.json"), PACKAGE_JSON).unwrap();
+        fs::write(dir.join("pnpm-lock.yaml"), lockfile).unwrap();
+
+        with_patcher(&dir.to_path_string(), |patcher| {
+            patcher.up_node_version().unwrap()
+        });
+
+        assert_eq!(
+            fs::read_to_string(dir.join("package.json")).unwrap(),
+            PACKAGE_JSON.replace("1.0.0", "1.0.1")
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("pnpm-lock.yaml")).unwrap(),
+            lockfile
+        );
+    }
+
+    #[test]
+    fn test_up_version_by_replacement_reports_missing_files_and_versions() {
+        let dir = TempDir::new("patcher_missing");
+        fs::write(
+            dir.join("package.json"),
+            PACKAGE_JSON.replace("1.0.0", "2.0.0"),
+        )
+        .unwrap();
+
+        with_patcher(&dir.to_path_string(), |patcher| {
+            assert!(matches!(
+                patcher.up_version_by_replacement("version.json", 1),
+                Err(CustomError::FileSystem(_))
+            ));
+            assert!(matches!(
+                patcher.up_version_by_replacement("package.json", 1),
+                Err(CustomError::VersionBuild(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_failed_post_patch_takes_release_commit_back() {
+        let dir = TempDir::new("patcher_post_patch");
+        let work = init_git_repo(&dir);
+        let base = commit_file(&work, "package.json", PACKAGE_JSON, "feat: package");
+        git(&work, &["push", "-q", "origin", "main"]);
+        let hooks_config = HooksConfig {
+            post_patch: vec![String::from("exit 1")],
+            ..HooksConfig::default()
+        };
+        let mut result = PatchResult::default();
+
+        with_hooks_patcher(
+            &work.to_string_lossy().to_string(),
+            &hooks_config,
+            |patcher| {
+                assert!(patcher.update_version_in_repo(&mut result).is_err());
+            },
+        );
+
+        assert_eq!(git(&work, &["rev-parse", "HEAD"]), base);
+        assert_eq!(git(&work, &["tag", "-l"]), "");
+        assert_eq!(git(&work, &["status", "--porcelain"]), "");
+        assert!(result.commit.is_none());
+        assert!(result.tag.is_none());
+    }
+
+    #[test]
+    fn test_failed_post_push_keeps_release() {
+        let dir = TempDir::new("patcher_post_push");
+        let work = init_git_repo(&dir);
+        commit_file(&work, "package.json", PACKAGE_JSON, "feat: package");
+        git(&work, &["push", "-q", "origin", "main"]);
+        // pre_pr stops the run before the PR is created in AWS
+        let hooks_config = HooksConfig {
+            post_push: vec![String::from("exit 1")],
+            pre_pr: vec![String::from("exit 2")],
+            ..HooksConfig::default()
+        };
+        let mut result = PatchResult::default();
+
+        with_hooks_patcher(
+            &work.to_string_lossy().to_string(),
+            &hooks_config,
+            |patcher| {
+                let error = patcher.update_version_in_repo(&mut result).unwrap_err();
+                assert!(error.to_string().contains("exit 2"));
+            },
+        );
+
+        let head = git(&work, &["rev-parse", "HEAD"]);
+        assert_eq!(git(&work, &["rev-parse", "origin/main"]), head);
+        assert!(!git(&work, &["ls-remote", "--tags", "origin", "release/1.0.1"]).is_empty());
+        assert_eq!(result.commit, Some(head));
+        assert_eq!(result.tag.as_deref(), Some("release/1.0.1"));
+        assert!(result.hook_error.unwrap().contains("exit 1"));
+    }
+
+    #[test]
+    fn test_up_pyproject_version_bumps_static_versions() {
+        let dir = TempDir::new("patcher_pyproject");
+        let pyproject = "[project]\nname = \"shared\"\nversion = \"1.0.0\"\n\n[tool.poetry]\nversion = '1.0.0'\n\n[tool.other]\nversion = \"1.0.0\"\n";
+        fs::write(dir.join("pyproject.toml"), pyproject).unwrap();
+
+        with_patcher(&dir.to_path_string(), |patcher| {
+            patcher.up_pyproject_version().unwrap()
+        });
+
+        assert_eq!(
+            fs::read_to_string(dir.join("pyproject.toml")).unwrap(),
+            pyproject.replacen("1.0.0", "1.0.1", 2)
+        );
+    }
+
+    #[test]
+    fn test_up_pyproject_version_checks_current_version() {
+        let dir = TempDir::new("patcher_pyproject_mismatch");
+        fs::write(
+            dir.join("pyproject.toml"),
+            "[project]\nname = \"shared\"\nversion = \"0.9.0\"\n",
+        )
+        .unwrap();
+
+        with_patcher(&dir.to_path_string(), |patcher| {
+            assert!(matches!(
+                patcher.up_pyproject_version(),
+                Err(CustomError::VersionBuild(_))
+            ));
+        });
+
+        let dynamic = TempDir::new("patcher_pyproject_dynamic");
+        let pyproject = "[project]\nname = \"shared\"\ndynamic = [\"version\"]\n";
+        fs::write(dynamic.join("pyproject.toml"), pyproject).unwrap();
+        with_patcher(&dynamic.to_path_string(), |patcher| {
+            patcher.up_pyproject_version().unwrap()
+        });
+        assert_eq!(
+            fs::read_to_string(dynamic.join("pyproject.toml")).unwrap(),
+            pyproject
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use std::path::Path;
+use std::process::{Command, Output};
+
+use crate::{
+    command_log::LoggedCommand,
+    config::Data,
+    custom_error::{CustomError, CustomResult},
+    logger::{Logger, LoggerTrait},
+    workers::release_branch::resolve_base,
+};
+
+// Read-only checks that run for every repo before anything is changed.
+pub struct PreflightChecker<'repo> {
+    pub path: &'repo str,
+    pub branch: &'repo String,
+    pub release_branch: &'repo String,
+    pub release_branch_base: Option<&'repo String>,
+    pub check_write_access: bool,
+}
+
+impl LoggerTrait for PreflightChecker<'_> {}
+impl PreflightChecker<'_> {
+    pub fn check(&self) -> Vec<String> {
+        let logger = self.get_logger();
+        logger.info(format!("Running pre-flight checks for repo: {}", self.path).as_str());
+
+        if !Path::new(self.path).join(".git").exists() {
+            return vec![format!("Repo not found at path: {}", self.path)];
+        }
+
+        let heads = match self.run_git(&[
+            "ls-remote",
+            "--heads",
+            "origin",
+            self.branch,
+            self.release_branch,
+        ]) {
+            Ok(output) => String::from_utf8_lossy(&output.stdout).to_string(),
+            Err(e) => return vec![format!("Remote is not reachable: {}", e)],
+        };
+
+        let mut problems = Vec::new();
+        for branch in [self.branch, self.release_branch] {
+            let reference = format!("refs/heads/{}", branch);
+            if heads.lines().any(|line| line.ends_with(&reference)) {
+                continue;
+            }
+            match self.release_branch_base {
+                Some(base) if branch == self.release_branch => {
+                    if let Err(e) = resolve_base(self.path, base) {
+                        problems.push(format!(
+                            "Branch '{}' does not exist on the remote and can't be created: {}",
+                            branch, e
+                        ));
+                    }
+                }
+                _ => problems.push(format!("Branch '{}' does not exist on the remote", branch)),
+            }
+        }
+
+        if self.check_write_access && problems.is_empty() {
+            if let Err(e) = self.check_push_access() {
+                problems.push(format!("No write access to the remote: {}", e));
+            }
+        }
+
+        problems
+    }
+
+    // A dry-run push of the branch onto itself authenticates against the remote
+    // without changing anything.
+    fn check_push_access(&self) -> CustomResult<()> {
+        self.run_git(&["fetch", "origin", self.branch])?;
+        let refspec = format!("refs/remotes/origin/{0}:refs/heads/{0}", self.branch);
+        self.run_git(&["push", "--dry-run", "--no-verify", "origin", &refspec])?;
+
+        Ok(())
+    }
+
+    fn run_git(&self, args: &[&str]) -> CustomResult<Output> {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(self.path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            return Err(CustomError::CommandExecution(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+
+        Ok(output)
+    }
+}
+
+pub fn preflight(config: &Data, repos: &[String]) -> Vec<(String, Vec<String>)> {
+    let logger = Logger::new();
+    logger.info(format!("Running pre-flight checks for {} repos", repos.len()).as_str());
+
+    let mut failures = Vec::new();
+    for repo in repos {
+        let path = Path::new(&config.root).join(repo);
+        let checker = PreflightChecker {
+            path: &path.to_string_lossy(),
+            branch: &config.git.branch,
+            release_branch: &config.git.release_branch,
+            release_branch_base: config.git.release_branch_base.as_ref(),
+            check_write_access: config.version_update_required,
+        };
+        let problems = checker.check();
+        if !problems.is_empty() {
+            failures.push((repo.clone(), problems));
+        }
+    }
+
+    failures
+}
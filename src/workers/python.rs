@@ -0,0 +1,243 @@
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use crate::{
+    command_log::LoggedCommand,
+    config::PythonConfig,
+    custom_error::{CustomError, CustomResult},
+    logger::LoggerTrait,
+};
+
+// pytest exits with 5 when there is nothing to collect
+const PYTEST_NO_TESTS: i32 = 5;
+
+// Installs a Python repo into a fresh virtualenv and checks it. The virtualenv lives
+// outside of the repo, so nothing it creates can end up in the release commit.
+pub struct PythonRebuilder<'repo> {
+    // Names the virtualenv, the repo may be checked out in a per-run worktree
+    pub repo_name: &'repo String,
+    pub repo: &'repo String,
+    pub config: &'repo PythonConfig,
+}
+
+impl LoggerTrait for PythonRebuilder<'_> {}
+impl PythonRebuilder<'_> {
+    pub fn rebuild(&self) -> CustomResult<()> {
+        let logger = self.get_logger();
+        let venv = self.get_venv_path();
+        logger.debug(format!("Creating virtualenv: {}", venv.display()).as_str());
+        if let Some(parent) = venv.parent() {
+            fs::create_dir_all(parent).map_err(|err| CustomError::FileSystem(err.to_string()))?;
+        }
+        let mut command = Command::new(&self.config.interpreter);
+        command.arg("-m").arg("venv").arg("--clear").arg(&venv);
+        self.run(command, "Failed to create virtualenv")?;
+
+        self.install(&venv)?;
+        if self.config.compile_check {
+            self.compile_check(&venv)?;
+        }
+        if self.config.run_tests {
+            self.run_tests(&venv)?;
+        }
+
+        Ok(())
+    }
+
+    fn install(&self, venv: &Path) -> CustomResult<()> {
+        let logger = self.get_logger();
+        let repo = Path::new(self.repo);
+        if repo.join("poetry.lock").exists() {
+            logger.debug("Installing packages with poetry");
+            let mut command = self.get_venv_command(venv, "poetry");
+            command
+                .arg("install")
+                .arg("--no-interaction")
+                .env("POETRY_VIRTUALENVS_CREATE", "false");
+            return self.run(command, "Failed to install packages with poetry");
+        }
+
+        let requirements = get_requirements_files(repo)?;
+        let has_pyproject = repo.join("pyproject.toml").exists();
+        if requirements.is_empty() && !has_pyproject {
+            logger.warn(format!("Nothing to install in repo: {}", self.repo).as_str());
+            return Ok(());
+        }
+        for file in requirements {
+            logger.debug(format!("Installing packages from {}", file).as_str());
+            let mut command = self.get_venv_command(venv, "pip");
+            command.arg("install").arg("-r").arg(&file);
+            self.run(command, "Failed to install requirements")?;
+        }
+        if has_pyproject {
+            logger.debug("Installing package from pyproject.toml");
+            let mut command = self.get_venv_command(venv, "pip");
+            command.arg("install").arg(".");
+            self.run(command, "Failed to install package")?;
+        }
+
+        Ok(())
+    }
+
+    fn compile_check(&self, venv: &Path) -> CustomResult<()> {
+        self.get_logger().debug("Compiling python sources");
+        let mut command = self.get_venv_command(venv, "python");
+        command
+            .arg("-m")
+            .arg("compileall")
+            .arg("-q")
+            .arg("-x")
+            .arg(r"(^|/)(\.git|node_modules)/")
+            .arg(".")
+            .env("PYTHONPYCACHEPREFIX", venv.join("pycache"));
+
+        self.run(command, "Compile check failed")
+    }
+
+    fn run_tests(&self, venv: &Path) -> CustomResult<()> {
+        let logger = self.get_logger();
+        // pytest comes from the dependencies of the repo, it is not installed on its own
+        let has_pytest = self
+            .get_venv_command(venv, "python")
+            .arg("-c")
+            .arg("import pytest")
+            .current_dir(self.repo)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?
+            .status
+            .success();
+        if !has_pytest {
+            return Err(CustomError::CommandExecution(format!(
+                "pytest is not installed in the virtualenv of repo: {}. Add it to the dev dependencies or disable python.run_tests",
+                self.repo
+            )));
+        }
+
+        logger.debug("Running pytest");
+        let mut command = self.get_venv_command(venv, "python");
+        command
+            .arg("-m")
+            .arg("pytest")
+            .arg("-q")
+            .arg("-p")
+            .arg("no:cacheprovider");
+        let output = command
+            .current_dir(self.repo)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if output.status.code() == Some(PYTEST_NO_TESTS) {
+            logger.warn(format!("No tests found in repo: {}", self.repo).as_str());
+            return Ok(());
+        }
+
+        self.check_output(output, "Tests failed")
+    }
+
+    // Runs a program from the virtualenv as if it was activated
+    fn get_venv_command(&self, venv: &Path, program: &str) -> Command {
+        let bin = venv.join("bin");
+        let program = if bin.join(program).exists() {
+            bin.join(program).into_os_string()
+        } else {
+            OsString::from(program)
+        };
+        let mut command = Command::new(program);
+        activate(&mut command, venv);
+        command
+    }
+
+    // Shell command running in the virtualenv of the last rebuild, if there is one
+    pub fn get_shell_command(&self, script: &str) -> CustomResult<Command> {
+        let venv = self.get_venv_path();
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script);
+        if venv.exists() {
+            activate(&mut command, &venv);
+        }
+
+        Ok(command)
+    }
+
+    pub fn get_venv_path(&self) -> PathBuf {
+        let root = match &self.config.venv_root {
+            Some(root) => PathBuf::from(root),
+            None => std::env::temp_dir().join("version_updater").join("venvs"),
+        };
+
+        root.join(self.repo_name)
+    }
+
+    fn run(&self, mut command: Command, error: &str) -> CustomResult<()> {
+        let output = command
+            .current_dir(self.repo)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+
+        self.check_output(output, error)
+    }
+
+    fn check_output(&self, output: Output, error: &str) -> CustomResult<()> {
+        if !output.status.success() {
+            let logger = self.get_logger();
+            logger.error(format!("{} in repo: {}", error, self.repo).as_str());
+            logger.error(format!("Output: {}", String::from_utf8_lossy(&output.stdout)).as_str());
+            logger.error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+
+            return Err(CustomError::CommandExecution(format!("{} in repo", error)));
+        }
+
+        Ok(())
+    }
+}
+
+fn activate(command: &mut Command, venv: &Path) {
+    let mut path = OsString::from(venv.join("bin"));
+    if let Some(existing) = std::env::var_os("PATH") {
+        path.push(":");
+        path.push(existing);
+    }
+    command
+        .env("VIRTUAL_ENV", venv)
+        .env("PATH", path)
+        .env("PYTHONDONTWRITEBYTECODE", "1")
+        .env("PIP_DISABLE_PIP_VERSION_CHECK", "1");
+}
+
+// `requirements.txt` first, then `requirements-*.txt` and the like in name order
+pub fn get_requirements_files(repo: &Path) -> CustomResult<Vec<String>> {
+    let mut files: Vec<String> = fs::read_dir(repo)
+        .map_err(|err| CustomError::FileSystem(err.to_string()))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with("requirements") && name.ends_with(".txt"))
+        .collect();
+    files.sort_by_key(|name| (name != "requirements.txt", name.clone()));
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn test_get_requirements_files_puts_main_file_first() {
+        let repo = TempDir::new("python");
+        for file in [
+            "requirements-dev.txt",
+            "requirements.txt",
+            "setup.py",
+            "requirements.in",
+        ] {
+            fs::write(repo.join(file), "").unwrap();
+        }
+
+        let files = get_requirements_files(&repo).unwrap();
+
+        assert_eq!(files, vec!["requirements.txt", "requirements-dev.txt"]);
+    }
+}
@@ -1,69 +1,95 @@
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use crate::{
-    config::RepoType,
+    command_log::LoggedCommand,
+    config::{PythonConfig, RepoType},
     custom_error::{CustomError, CustomResult},
     logger::LoggerTrait,
+    workers::{
+        cleaner::{CleanResult, RepoCleaner},
+        python::PythonRebuilder,
+    },
 };
 
 pub struct RepoRebuilder<'repo> {
+    pub repo_name: &'repo String,
     pub repo: &'repo String,
     pub repo_type: RepoType,
+    pub clean_targets: &'repo [String],
+    pub python: &'repo PythonConfig,
 }
 
 impl<'config> LoggerTrait for RepoRebuilder<'config> {}
 impl<'repo> RepoRebuilder<'repo> {
-    pub fn rebuild_repo(&self) -> CustomResult<()> {
+    pub fn rebuild_repo(&self) -> CustomResult<CleanResult> {
         let logger = self.get_logger();
         logger.debug(format!("Rebuilding repo: {}", self.repo).as_str());
+        let cleaner = RepoCleaner {
+            repo: self.repo,
+            targets: self.clean_targets,
+        };
+        let clean_result = cleaner.clean()?;
         match self.repo_type {
-            RepoType::Node => {
-                self.rebuild_node_repo()?;
-                Ok(())
-            }
+            RepoType::Node => self.rebuild_node_repo()?,
             RepoType::Python => {
-                logger.debug(format!("Nothing to rebuild in repo='{}'", self.repo).as_str());
-                Ok(())
+                let rebuilder = PythonRebuilder {
+                    repo_name: self.repo_name,
+                    repo: self.repo,
+                    config: self.python,
+                };
+                rebuilder.rebuild()?;
             }
         }
-    }
-
-    fn rebuild_node_repo(&self) -> CustomResult<()> {
-        self.delete_folders()?;
-        self.install_npm_packages()?;
-        self.build_node_repo()?;
 
-        Ok(())
+        Ok(clean_result)
     }
 
-    fn delete_folders(&self) -> CustomResult<()> {
+    fn rebuild_node_repo(&self) -> CustomResult<()> {
         let logger = self.get_logger();
-        logger.debug("Deleting node_modules and dist folders");
-        let output = Command::new("rm")
-            .arg("-rf")
-            .current_dir(self.repo)
-            .output()
-            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
-        if !output.status.success() {
-            logger.error(format!("Failed to delete folders in repo: {}", self.repo).as_str());
-            logger.error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+        let package_manager = PackageManager::detect(Path::new(self.repo));
+        logger.debug(
+            format!(
+                "Detected package manager for repo: {}. {:?}",
+                self.repo, package_manager
+            )
+            .as_str(),
+        );
+        let lockfile = Path::new(self.repo).join(package_manager.get_lockfile());
+        let lockfile_before = read_lockfile(&lockfile)?;
 
-            return Err(CustomError::CommandExecution(
-                "Failed to delete folders in repo".to_string(),
-            ));
+        self.install_packages(package_manager, lockfile_before.is_some())?;
+        self.build_node_repo(package_manager)?;
+
+        if read_lockfile(&lockfile)? != lockfile_before {
+            logger.error(
+                format!(
+                    "Lockfile changed during the rebuild: {}",
+                    lockfile.display()
+                )
+                .as_str(),
+            );
+            return Err(CustomError::CommandExecution(format!(
+                "Lockfile {} changed during the rebuild",
+                package_manager.get_lockfile()
+            )));
         }
-        logger.debug("Deleted node_modules and dis folders");
 
         Ok(())
     }
 
-    fn install_npm_packages(&self) -> CustomResult<()> {
+    fn install_packages(
+        &self,
+        package_manager: PackageManager,
+        has_lockfile: bool,
+    ) -> CustomResult<()> {
         let logger = self.get_logger();
         logger.debug("Installing packages");
-        let output = Command::new("npm")
-            .arg("install")
+        let output = Command::new(package_manager.get_program())
+            .args(package_manager.get_install_args(Path::new(self.repo), has_lockfile))
             .current_dir(self.repo)
-            .output()
+            .logged_output()
             .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
         if !output.status.success() {
             logger.error(format!("Failed to install packages in repo: {}", self.repo).as_str());
@@ -78,14 +104,14 @@ impl<'repo> RepoRebuilder<'repo> {
         Ok(())
     }
 
-    fn build_node_repo(&self) -> CustomResult<()> {
+    fn build_node_repo(&self, package_manager: PackageManager) -> CustomResult<()> {
         let logger = self.get_logger();
         logger.debug("Building node repo");
-        let output = Command::new("npm")
+        let output = Command::new(package_manager.get_program())
             .arg("run")
             .arg("build")
             .current_dir(self.repo)
-            .output()
+            .logged_output()
             .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
         if !output.status.success() {
             logger.error(format!("Failed to build node repo: {}", self.repo).as_str());
@@ -100,3 +126,127 @@ impl<'repo> RepoRebuilder<'repo> {
         Ok(())
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageManager {
+    Npm,
+    Yarn,
+    Pnpm,
+}
+
+impl PackageManager {
+    // The lockfile decides. Repos without one are installed with npm.
+    pub fn detect(repo: &Path) -> Self {
+        if repo.join("pnpm-lock.yaml").exists() {
+            return PackageManager::Pnpm;
+        }
+        if repo.join("yarn.lock").exists() {
+            return PackageManager::Yarn;
+        }
+
+        PackageManager::Npm
+    }
+
+    pub fn get_program(&self) -> &'static str {
+        match self {
+            PackageManager::Npm => "npm",
+            PackageManager::Yarn => "yarn",
+            PackageManager::Pnpm => "pnpm",
+        }
+    }
+
+    pub fn get_lockfile(&self) -> &'static str {
+        match self {
+            PackageManager::Npm => "package-lock.json",
+            PackageManager::Yarn => "yarn.lock",
+            PackageManager::Pnpm => "pnpm-lock.yaml",
+        }
+    }
+
+    // Installs exactly what the lockfile says and fails if it is out of date
+    fn get_install_args(&self, repo: &Path, has_lockfile: bool) -> Vec<&'static str> {
+        match self {
+            PackageManager::Npm if has_lockfile => vec!["ci"],
+            PackageManager::Npm => vec!["install", "--no-package-lock"],
+            // Yarn 2+ is configured with `.yarnrc.yml` and renamed the flag
+            PackageManager::Yarn if repo.join(".yarnrc.yml").exists() => {
+                vec!["install", "--immutable"]
+            }
+            PackageManager::Yarn => vec!["install", "--frozen-lockfile"],
+            PackageManager::Pnpm => vec!["install", "--frozen-lockfile"],
+        }
+    }
+
+    // Rewrites the lockfile after `package.json` changed, without running scripts
+    pub fn get_lockfile_update_args(&self, repo: &Path) -> Vec<&'static str> {
+        match self {
+            PackageManager::Npm => vec!["install", "--package-lock-only", "--ignore-scripts"],
+            PackageManager::Yarn if repo.join(".yarnrc.yml").exists() => {
+                vec!["install", "--mode", "update-lockfile"]
+            }
+            // Yarn 1 can't update only the lockfile, the packages are installed as well
+            PackageManager::Yarn => vec!["install", "--ignore-scripts"],
+            PackageManager::Pnpm => vec!["install", "--lockfile-only", "--ignore-scripts"],
+        }
+    }
+}
+
+// Installed packages of a node repo, the virtualenv of a python repo
+pub fn get_build_output(
+    repo_name: &String,
+    repo: &String,
+    repo_type: RepoType,
+    python: &PythonConfig,
+) -> PathBuf {
+    match repo_type {
+        RepoType::Node => Path::new(repo).join("node_modules"),
+        RepoType::Python => PythonRebuilder {
+            repo_name,
+            repo,
+            config: python,
+        }
+        .get_venv_path(),
+    }
+}
+
+fn read_lockfile(path: &Path) -> CustomResult<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(CustomError::FileSystem(err.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn test_detect_package_manager_from_lockfile() {
+        let repo = TempDir::new("rebuilder");
+
+        assert_eq!(PackageManager::detect(&repo), PackageManager::Npm);
+        assert_eq!(
+            PackageManager::Npm.get_install_args(&repo, false),
+            vec!["install", "--no-package-lock"]
+        );
+        fs::write(repo.join("yarn.lock"), "").unwrap();
+        assert_eq!(PackageManager::detect(&repo), PackageManager::Yarn);
+        assert_eq!(
+            PackageManager::Yarn.get_install_args(&repo, true),
+            vec!["install", "--frozen-lockfile"]
+        );
+        assert_eq!(
+            PackageManager::Yarn.get_lockfile_update_args(&repo),
+            vec!["install", "--ignore-scripts"]
+        );
+        fs::write(repo.join(".yarnrc.yml"), "").unwrap();
+        assert_eq!(
+            PackageManager::Yarn.get_lockfile_update_args(&repo),
+            vec!["install", "--mode", "update-lockfile"]
+        );
+        fs::write(repo.join("pnpm-lock.yaml"), "").unwrap();
+        assert_eq!(PackageManager::detect(&repo), PackageManager::Pnpm);
+    }
+}
@@ -0,0 +1,133 @@
+use std::process::Command;
+
+use crate::{
+    command_log::LoggedCommand,
+    custom_error::{CustomError, CustomResult},
+    logger::LoggerTrait,
+};
+
+pub struct ReleaseBranchCreator<'repo> {
+    pub path: &'repo str,
+    pub release_branch: &'repo String,
+    pub base: &'repo String,
+    pub disable_checks: bool,
+}
+
+impl LoggerTrait for ReleaseBranchCreator<'_> {}
+impl ReleaseBranchCreator<'_> {
+    // Creates the release branch on the remote from the configured base if it is missing.
+    // Returns the commit it was created from.
+    pub fn ensure_exists(&self, dry_run: bool) -> CustomResult<Option<String>> {
+        let logger = self.get_logger();
+        if self.exists_on_remote()? {
+            return Ok(None);
+        }
+
+        let commit = resolve_base(self.path, self.base)?;
+        if dry_run {
+            logger.warn(
+                format!(
+                    "Dry run mode. Release branch {} would be created from {} ({}) in repo: {}",
+                    self.release_branch, self.base, commit, self.path
+                )
+                .as_str(),
+            );
+            return Ok(None);
+        }
+
+        logger.info(
+            format!(
+                "Creating release branch {} from {} ({}) in repo: {}",
+                self.release_branch, self.base, commit, self.path
+            )
+            .as_str(),
+        );
+        let mut command = Command::new("git");
+        command
+            .arg("push")
+            .arg("origin")
+            .arg(format!("{}:refs/heads/{}", commit, self.release_branch));
+        if self.disable_checks {
+            command.arg("--no-verify");
+        }
+        let output = command
+            .current_dir(self.path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            logger
+                .error(format!("Failed to create release branch for repo: {}", self.path).as_str());
+            logger.error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+
+            return Err(CustomError::CommandExecution(
+                "Failed to create release branch for repo".to_string(),
+            ));
+        }
+        logger.warn(
+            format!(
+                "Created release branch {} in repo: {}",
+                self.release_branch, self.path
+            )
+            .as_str(),
+        );
+
+        Ok(Some(commit))
+    }
+
+    fn exists_on_remote(&self) -> CustomResult<bool> {
+        let output = Command::new("git")
+            .arg("ls-remote")
+            .arg("--exit-code")
+            .arg("--heads")
+            .arg("origin")
+            .arg(self.release_branch)
+            .current_dir(self.path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+
+        match output.status.code() {
+            Some(0) => Ok(true),
+            Some(2) => Ok(false),
+            _ => Err(CustomError::CommandExecution(format!(
+                "Failed to list remote branches: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))),
+        }
+    }
+}
+
+// The base can be a remote branch, a tag or a commit. Remote branches win over local ones.
+pub fn resolve_base(path: &str, base: &str) -> CustomResult<String> {
+    let output = Command::new("git")
+        .arg("fetch")
+        .arg("origin")
+        .arg("--tags")
+        .current_dir(path)
+        .logged_output()
+        .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+    if !output.status.success() {
+        return Err(CustomError::CommandExecution(format!(
+            "Failed to fetch repo: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    for candidate in [format!("origin/{}", base), base.to_string()] {
+        let output = Command::new("git")
+            .arg("rev-parse")
+            .arg("--verify")
+            .arg("-q")
+            .arg(format!("{}^{{commit}}", candidate))
+            .current_dir(path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if output.status.success() {
+            return Ok(String::from_utf8_lossy(&output.stdout).trim().to_string());
+        }
+    }
+
+    Err(CustomError::CommandExecution(format!(
+        "Unknown release branch base: {}",
+        base
+    )))
+}
@@ -0,0 +1,388 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    config::{ReleaseNotesConfig, ReleaseNotesFormat},
+    custom_error::{CustomError, CustomResult},
+    logger::LoggerTrait,
+    report::{RepoReport, RepoStatus, RunReport},
+    workers::tickets::TicketGroup,
+};
+
+// Conventional Commit types in the order they are listed in the release notes.
+// Unknown types follow alphabetically, commits without a type go last.
+const TYPE_ORDER: [&str; 10] = [
+    "feat", "fix", "perf", "refactor", "revert", "docs", "test", "build", "ci", "chore",
+];
+const NO_TYPE: &str = "other";
+const NO_TICKET: &str = "No ticket";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommitTypeGroup {
+    pub kind: String,
+    pub subjects: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ReleaseNotes<'report> {
+    run_id: &'report str,
+    branch: &'report str,
+    release_branch: &'report str,
+    repos: Vec<RepoNotes<'report>>,
+}
+
+#[derive(Serialize)]
+struct RepoNotes<'report> {
+    name: &'report str,
+    current_version: Option<&'report str>,
+    next_version: Option<&'report str>,
+    tag: Option<&'report str>,
+    pr_link: Option<&'report str>,
+    changes: &'report [CommitTypeGroup],
+    tickets: Option<&'report [TicketGroup]>,
+}
+
+pub struct ReleaseNotesWriter<'report> {
+    pub report: &'report RunReport,
+    pub config: &'report ReleaseNotesConfig,
+}
+
+impl LoggerTrait for ReleaseNotesWriter<'_> {}
+impl ReleaseNotesWriter<'_> {
+    // Writes `<dir>/<run-id>.<ext>` for every configured format
+    pub fn write(&self) -> CustomResult<Vec<PathBuf>> {
+        let logger = self.get_logger();
+        if !self.config.enabled {
+            return Ok(Vec::new());
+        }
+
+        fs::create_dir_all(&self.config.dir)
+            .map_err(|err| CustomError::FileSystem(err.to_string()))?;
+        let mut paths = Vec::new();
+        for format in self.config.formats.iter() {
+            let path = Path::new(&self.config.dir).join(format!(
+                "{}.{}",
+                self.report.run_id,
+                get_extension(*format)
+            ));
+            fs::write(&path, render(self.report, *format)?)
+                .map_err(|err| CustomError::FileSystem(err.to_string()))?;
+            logger.info(format!("Wrote release notes: {}", path.display()).as_str());
+            paths.push(path);
+        }
+
+        Ok(paths)
+    }
+}
+
+// Renders the notes of every repo that got a new version, released or dry run
+pub fn render(report: &RunReport, format: ReleaseNotesFormat) -> CustomResult<String> {
+    let notes = ReleaseNotes {
+        run_id: &report.run_id,
+        branch: &report.branch,
+        release_branch: &report.release_branch,
+        repos: report
+            .repos
+            .iter()
+            .filter(|repo| matches!(repo.status, RepoStatus::Released | RepoStatus::DryRun))
+            .map(get_repo_notes)
+            .collect(),
+    };
+
+    match format {
+        ReleaseNotesFormat::Markdown => Ok(render_markdown(&notes)),
+        ReleaseNotesFormat::Json => serde_json::to_string_pretty(&notes)
+            .map_err(|err| CustomError::FileSystem(err.to_string())),
+        ReleaseNotesFormat::Html => Ok(render_html(&notes)),
+        ReleaseNotesFormat::Plain => Ok(render_plain(&notes)),
+    }
+}
+
+// Plain text changes of a single repo, used for logs and the PR description
+pub fn render_changes(changes: &[CommitTypeGroup], tickets: Option<&[TicketGroup]>) -> String {
+    let mut result = String::new();
+    match tickets {
+        Some(tickets) => {
+            for ticket in tickets {
+                result.push_str(&format!("{}\n", get_ticket_title(ticket)));
+                push_plain_types(&mut result, &ticket.types, "  ");
+            }
+        }
+        None => push_plain_types(&mut result, changes, ""),
+    }
+
+    result
+}
+
+pub fn group_by_type(subjects: &[&str]) -> Vec<CommitTypeGroup> {
+    let mut groups: Vec<CommitTypeGroup> = Vec::new();
+    for subject in subjects {
+        let kind = get_commit_type(subject);
+        let subject = subject.trim().to_string();
+        match groups.iter_mut().find(|group| group.kind == kind) {
+            Some(group) => group.subjects.push(subject),
+            None => groups.push(CommitTypeGroup {
+                kind,
+                subjects: vec![subject],
+            }),
+        }
+    }
+    groups.sort_by_key(|group| {
+        let rank = match TYPE_ORDER.iter().position(|kind| *kind == group.kind) {
+            Some(position) => position,
+            None if group.kind == NO_TYPE => TYPE_ORDER.len() + 1,
+            None => TYPE_ORDER.len(),
+        };
+        (rank, group.kind.clone())
+    });
+
+    groups
+}
+
+// `feat(api)!: add export` -> `feat`. Subjects that don't follow the convention are `other`.
+pub fn get_commit_type(subject: &str) -> String {
+    let prefix = match subject.split_once(':') {
+        Some((prefix, _)) => prefix.trim(),
+        None => return NO_TYPE.to_string(),
+    };
+    let kind = prefix
+        .split('(')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('!');
+    if kind.is_empty() || !kind.chars().all(|char| char.is_ascii_alphabetic()) {
+        return NO_TYPE.to_string();
+    }
+
+    kind.to_lowercase()
+}
+
+fn get_repo_notes(repo: &RepoReport) -> RepoNotes<'_> {
+    RepoNotes {
+        name: &repo.name,
+        current_version: repo.current_version.as_deref(),
+        next_version: repo.next_version.as_deref(),
+        tag: repo.tag.as_deref(),
+        pr_link: repo.pr_link.as_deref(),
+        changes: repo.changes.as_deref().unwrap_or_default(),
+        tickets: repo.tickets.as_deref(),
+    }
+}
+
+fn get_extension(format: ReleaseNotesFormat) -> &'static str {
+    match format {
+        ReleaseNotesFormat::Markdown => "md",
+        ReleaseNotesFormat::Json => "json",
+        ReleaseNotesFormat::Html => "html",
+        ReleaseNotesFormat::Plain => "txt",
+    }
+}
+
+fn get_versions(repo: &RepoNotes) -> String {
+    format!(
+        "{} -> {}",
+        repo.current_version.unwrap_or("?"),
+        repo.next_version.unwrap_or("?")
+    )
+}
+
+fn get_ticket_title(ticket: &TicketGroup) -> String {
+    match (&ticket.key, &ticket.link) {
+        (Some(key), Some(link)) => format!("{} ({})", key, link),
+        (Some(key), None) => key.clone(),
+        (None, _) => NO_TICKET.to_string(),
+    }
+}
+
+fn push_plain_types(result: &mut String, types: &[CommitTypeGroup], indent: &str) {
+    for group in types {
+        result.push_str(&format!("{}{}\n", indent, group.kind));
+        for subject in group.subjects.iter() {
+            result.push_str(&format!("{}  - {}\n", indent, subject));
+        }
+    }
+}
+
+fn render_plain(notes: &ReleaseNotes) -> String {
+    let mut result = format!(
+        "Release notes {} ({} -> {})\n",
+        notes.run_id, notes.branch, notes.release_branch
+    );
+    for repo in notes.repos.iter() {
+        result.push_str(&format!("\n{} {}\n", repo.name, get_versions(repo)));
+        if let Some(tag) = repo.tag {
+            result.push_str(&format!("Tag: {}\n", tag));
+        }
+        if let Some(pr_link) = repo.pr_link {
+            result.push_str(&format!("PR: {}\n", pr_link));
+        }
+        result.push_str(&render_changes(repo.changes, repo.tickets));
+    }
+
+    result
+}
+
+fn render_markdown(notes: &ReleaseNotes) -> String {
+    let mut result = format!(
+        "# Release notes {}\n\n`{}` -> `{}`\n",
+        notes.run_id, notes.branch, notes.release_branch
+    );
+    for repo in notes.repos.iter() {
+        result.push_str(&format!("\n## {} {}\n\n", repo.name, get_versions(repo)));
+        if let Some(tag) = repo.tag {
+            result.push_str(&format!("- Tag: `{}`\n", tag));
+        }
+        if let Some(pr_link) = repo.pr_link {
+            result.push_str(&format!("- PR: <{}>\n", pr_link));
+        }
+        match repo.tickets {
+            Some(tickets) => {
+                for ticket in tickets {
+                    let title = match (&ticket.key, &ticket.link) {
+                        (Some(key), Some(link)) => format!("[{}]({})", key, link),
+                        (Some(key), None) => key.clone(),
+                        (None, _) => NO_TICKET.to_string(),
+                    };
+                    result.push_str(&format!("\n### {}\n", title));
+                    push_markdown_types(&mut result, &ticket.types, "####");
+                }
+            }
+            None => push_markdown_types(&mut result, repo.changes, "###"),
+        }
+    }
+
+    result
+}
+
+fn push_markdown_types(result: &mut String, types: &[CommitTypeGroup], heading: &str) {
+    for group in types {
+        result.push_str(&format!("\n{} {}\n\n", heading, group.kind));
+        for subject in group.subjects.iter() {
+            result.push_str(&format!("- {}\n", subject));
+        }
+    }
+}
+
+fn render_html(notes: &ReleaseNotes) -> String {
+    let mut result = format!(
+        "<h1>Release notes {}</h1>\n<p><code>{}</code> -&gt; <code>{}</code></p>\n",
+        escape_html(notes.run_id),
+        escape_html(notes.branch),
+        escape_html(notes.release_branch)
+    );
+    for repo in notes.repos.iter() {
+        result.push_str(&format!(
+            "<h2>{} {}</h2>\n",
+            escape_html(repo.name),
+            escape_html(&get_versions(repo))
+        ));
+        if repo.tag.is_some() || repo.pr_link.is_some() {
+            result.push_str("<ul>\n");
+            if let Some(tag) = repo.tag {
+                result.push_str(&format!(
+                    "<li>Tag: <code>{}</code></li>\n",
+                    escape_html(tag)
+                ));
+            }
+            if let Some(pr_link) = repo.pr_link {
+                let pr_link = escape_html(pr_link);
+                result.push_str(&format!("<li>PR: <a href=\"{0}\">{0}</a></li>\n", pr_link));
+            }
+            result.push_str("</ul>\n");
+        }
+        match repo.tickets {
+            Some(tickets) => {
+                for ticket in tickets {
+                    let title = match (&ticket.key, &ticket.link) {
+                        (Some(key), Some(link)) => {
+                            format!("<a href=\"{}\">{}</a>", escape_html(link), escape_html(key))
+                        }
+                        (Some(key), None) => escape_html(key),
+                        (None, _) => NO_TICKET.to_string(),
+                    };
+                    result.push_str(&format!("<h3>{}</h3>\n", title));
+                    push_html_types(&mut result, &ticket.types, "h4");
+                }
+            }
+            None => push_html_types(&mut result, repo.changes, "h3"),
+        }
+    }
+
+    result
+}
+
+fn push_html_types(result: &mut String, types: &[CommitTypeGroup], heading: &str) {
+    for group in types {
+        result.push_str(&format!(
+            "<{0}>{1}</{0}>\n<ul>\n",
+            heading,
+            escape_html(&group.kind)
+        ));
+        for subject in group.subjects.iter() {
+            result.push_str(&format!("<li>{}</li>\n", escape_html(subject)));
+        }
+        result.push_str("</ul>\n");
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_report() -> RunReport {
+        let mut report = RunReport::new("dev", "master");
+        report.run_id = "20240101-100000".to_string();
+        let repo = report.add_repo("api", "/repos/api");
+        repo.status = RepoStatus::Released;
+        repo.current_version = Some("1.0.0".to_string());
+        repo.next_version = Some("1.0.1".to_string());
+        repo.tag = Some("release/1.0.1".to_string());
+        repo.pr_link = Some("https://example.com/pr/1".to_string());
+        repo.changes = Some(group_by_type(&["fix: a < b", "feat: export"]));
+        let skipped = report.add_repo("web", "/repos/web");
+        skipped.status = RepoStatus::Skipped;
+
+        report
+    }
+
+    #[test]
+    fn test_group_by_type_orders_known_types_first() {
+        let groups = group_by_type(&[
+            "update readme",
+            "chore: bump",
+            "fix(api)!: nulls",
+            "feat: export",
+        ]);
+
+        let kinds: Vec<&str> = groups.iter().map(|group| group.kind.as_str()).collect();
+        assert_eq!(kinds, vec!["feat", "fix", "chore", "other"]);
+    }
+
+    #[test]
+    fn test_render_markdown_lists_released_repos() {
+        let notes = render(&get_report(), ReleaseNotesFormat::Markdown).unwrap();
+
+        assert_eq!(
+            notes,
+            "# Release notes 20240101-100000\n\n`dev` -> `master`\n\n## api 1.0.0 -> 1.0.1\n\n- Tag: `release/1.0.1`\n- PR: <https://example.com/pr/1>\n\n### feat\n\n- feat: export\n\n### fix\n\n- fix: a < b\n"
+        );
+    }
+
+    #[test]
+    fn test_render_html_escapes_subjects() {
+        let notes = render(&get_report(), ReleaseNotesFormat::Html).unwrap();
+
+        assert!(notes.contains("<li>fix: a &lt; b</li>"));
+        assert!(!notes.contains("web"));
+    }
+}
@@ -0,0 +1,148 @@
+use std::process::Command;
+
+use glob::{MatchOptions, Pattern};
+
+use crate::{
+    command_log::LoggedCommand,
+    config::ReleaseRulesConfig,
+    custom_error::{CustomError, CustomResult},
+    logger::LoggerTrait,
+    workers::{
+        history::{get_pathspecs, Commit},
+        release_notes::get_commit_type,
+    },
+};
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+// Decides whether the commits since the last release are worth a release
+pub struct ReleaseRulesChecker<'repo> {
+    pub path: &'repo String,
+    pub config: &'repo ReleaseRulesConfig,
+    // Path scope of the repo history, files outside of it are not looked at
+    pub include: &'repo [String],
+    pub exclude: &'repo [String],
+}
+
+impl LoggerTrait for ReleaseRulesChecker<'_> {}
+impl ReleaseRulesChecker<'_> {
+    // A commit is release-relevant when its type is not ignored and it changes at least
+    // one file outside of the ignored paths. Returns the skip reason when none is.
+    pub fn get_skip_reason(&self, history: &[Commit]) -> CustomResult<Option<String>> {
+        let logger = self.get_logger();
+        let ignore_paths = self
+            .config
+            .ignore_paths
+            .iter()
+            .map(|pattern| {
+                Pattern::new(pattern).map_err(|err| {
+                    CustomError::ConfigParsingError(format!(
+                        "Invalid release_rules.ignore_paths glob `{}`: {}",
+                        pattern, err
+                    ))
+                })
+            })
+            .collect::<CustomResult<Vec<Pattern>>>()?;
+
+        let mut ignored_types = 0;
+        let mut ignored_paths = 0;
+        for commit in history {
+            if self.is_ignored_type(commit) {
+                ignored_types += 1;
+                continue;
+            }
+            let files = self.get_changed_files(commit)?;
+            if files.iter().all(|file| is_ignored(file, &ignore_paths)) {
+                ignored_paths += 1;
+                continue;
+            }
+            logger.debug(
+                format!(
+                    "Release-relevant change in repo: {}. {} {}",
+                    self.path, commit.hash, commit.subject
+                )
+                .as_str(),
+            );
+            return Ok(None);
+        }
+
+        Ok(Some(format!(
+            "No release-relevant changes in {} commits: {} with ignored types, {} touching only ignored paths",
+            history.len(),
+            ignored_types,
+            ignored_paths
+        )))
+    }
+
+    fn is_ignored_type(&self, commit: &Commit) -> bool {
+        let kind = get_commit_type(&commit.subject);
+        self.config
+            .ignore_types
+            .iter()
+            .any(|ignored| ignored.trim_end_matches(':').eq_ignore_ascii_case(&kind))
+    }
+
+    // Merge commits are compared with their first parent, so only what they bring in counts
+    fn get_changed_files(&self, commit: &Commit) -> CustomResult<Vec<String>> {
+        let output = Command::new("git")
+            .arg("show")
+            .arg("--format=")
+            .arg("--name-only")
+            .arg("--diff-merges=first-parent")
+            .arg(&commit.hash)
+            .arg("--")
+            .args(get_pathspecs(self.include, self.exclude))
+            .current_dir(self.path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            let logger = self.get_logger();
+            logger.error(format!("Failed to list changed files for repo: {}", self.path).as_str());
+            logger.error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+
+            return Err(CustomError::CommandExecution(
+                "Failed to list changed files for repo".to_string(),
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect())
+    }
+}
+
+fn is_ignored(file: &str, ignore_paths: &[Pattern]) -> bool {
+    ignore_paths
+        .iter()
+        .any(|pattern| pattern.matches_with(file, MATCH_OPTIONS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_patterns(patterns: &[&str]) -> Vec<Pattern> {
+        patterns
+            .iter()
+            .map(|pattern| Pattern::new(pattern).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_is_ignored_matches_nested_and_root_files() {
+        let patterns = get_patterns(&["**/*.md", ".github/**", "test/**"]);
+
+        assert!(is_ignored("README.md", &patterns));
+        assert!(is_ignored("docs/guide/setup.md", &patterns));
+        assert!(is_ignored(".github/workflows/ci.yml", &patterns));
+        assert!(is_ignored("test/unit/api.test.js", &patterns));
+        assert!(!is_ignored("src/test/helpers.js", &patterns));
+        assert!(!is_ignored("package.json", &patterns));
+    }
+}
@@ -0,0 +1,526 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use crate::{
+    command_log::LoggedCommand,
+    config::{CheckoutPolicy, Data},
+    custom_error::{CustomError, CustomResult},
+    logger::{Logger, LoggerTrait},
+    report::{RepoReport, RunReport},
+    workers::branch::BranchSwitcher,
+    workers::loginer::{get_switch_role_command, login},
+    workers::worktree::{get_worktree_root, WorktreeManager},
+};
+
+#[derive(Debug)]
+pub enum RollbackStep {
+    DeleteLocalTag(String),
+    DeleteRemoteTag(String),
+    RevertCommit(String),
+    ResetCommit(String),
+    ClosePullRequest(String),
+    DeleteReleaseBranch(String),
+}
+
+impl Display for RollbackStep {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Self::DeleteLocalTag(tag) => write!(f, "delete local tag {}", tag),
+            Self::DeleteRemoteTag(tag) => write!(f, "delete remote tag {}", tag),
+            Self::RevertCommit(commit) => write!(f, "revert commit {}", commit),
+            Self::ResetCommit(commit) => write!(f, "reset branch to the parent of {}", commit),
+            Self::ClosePullRequest(id) => write!(f, "close PR {}", id),
+            Self::DeleteReleaseBranch(branch) => {
+                write!(f, "delete release branch {} created by the run", branch)
+            }
+        }
+    }
+}
+
+pub struct RollbackRunner<'repo> {
+    pub repo: &'repo RepoReport,
+    pub branch: &'repo String,
+    pub release_branch: &'repo String,
+    pub role: &'repo String,
+    pub sso_script_path: &'repo String,
+    pub disable_checks: bool,
+    pub reset: bool,
+    pub checkout_policy: CheckoutPolicy,
+    pub run_id: &'repo String,
+    // Set when worktree mode is enabled, the developer's checkout is not touched then
+    pub worktree_root: Option<&'repo PathBuf>,
+}
+
+impl LoggerTrait for RollbackRunner<'_> {}
+impl RollbackRunner<'_> {
+    pub fn get_steps(&self) -> Vec<RollbackStep> {
+        let mut steps = Vec::new();
+        if let Some(tag) = &self.repo.tag {
+            steps.push(RollbackStep::DeleteLocalTag(tag.clone()));
+            steps.push(RollbackStep::DeleteRemoteTag(tag.clone()));
+        }
+        if let Some(commit) = &self.repo.release_commit {
+            if self.reset {
+                steps.push(RollbackStep::ResetCommit(commit.clone()));
+            } else {
+                steps.push(RollbackStep::RevertCommit(commit.clone()));
+            }
+        }
+        if let Some(pr_id) = &self.repo.pr_id {
+            steps.push(RollbackStep::ClosePullRequest(pr_id.clone()));
+        }
+        if self.repo.release_branch_created_from.is_some() {
+            steps.push(RollbackStep::DeleteReleaseBranch(
+                self.release_branch.clone(),
+            ));
+        }
+
+        steps
+    }
+
+    pub fn rollback(&self) -> CustomResult<()> {
+        let logger = self.get_logger();
+        logger.info(format!("Rolling back repo: {}", self.repo.path).as_str());
+
+        match self.worktree_root {
+            Some(worktree_root) => self.rollback_in_worktree(worktree_root)?,
+            None => self.rollback_in_checkout()?,
+        }
+        logger.info(format!("Rolled back repo: {}", self.repo.path).as_str());
+
+        Ok(())
+    }
+
+    fn rollback_in_checkout(&self) -> CustomResult<()> {
+        let switcher = BranchSwitcher {
+            target_branch: self.branch,
+            policy: self.checkout_policy,
+        };
+        let state = switcher.checkout_target_branch(&self.repo.path)?;
+
+        // A failed rollback is the error that matters, a failed restore is only logged then
+        let result = self.run_steps(&self.repo.path);
+        let restored = switcher.restore(&state);
+        if let Err(e) = &restored {
+            self.get_logger()
+                .error(format!("Failed to restore repo: {}. {}", self.repo.path, e).as_str());
+        }
+        result?;
+        restored
+    }
+
+    fn rollback_in_worktree(&self, worktree_root: &Path) -> CustomResult<()> {
+        let manager = WorktreeManager {
+            repo_path: &self.repo.path,
+            branch: self.branch,
+        };
+        let worktree_path =
+            worktree_root.join(format!("{}-rollback-{}", self.repo.name, self.run_id));
+        let path = manager.create(&worktree_path)?;
+
+        let result = self.run_steps(&path);
+        let removed = manager.remove(&path);
+        if let Err(e) = &removed {
+            self.get_logger()
+                .error(format!("Failed to remove worktree: {}. {}", path, e).as_str());
+        }
+        result?;
+        removed
+    }
+
+    fn run_steps(&self, path: &str) -> CustomResult<()> {
+        let logger = self.get_logger();
+        for step in self.get_steps() {
+            logger.info(format!("Rollback step for repo {}: {}", self.repo.name, step).as_str());
+            match step {
+                RollbackStep::DeleteLocalTag(tag) => self.delete_local_tag(path, &tag)?,
+                RollbackStep::DeleteRemoteTag(tag) => {
+                    self.delete_remote_ref(path, &format!("refs/tags/{}", tag))?
+                }
+                RollbackStep::RevertCommit(commit) => self.revert_commit(path, &commit)?,
+                RollbackStep::ResetCommit(commit) => self.reset_commit(path, &commit)?,
+                RollbackStep::ClosePullRequest(pr_id) => self.close_pr(path, &pr_id)?,
+                RollbackStep::DeleteReleaseBranch(branch) => {
+                    self.delete_remote_ref(path, &format!("refs/heads/{}", branch))?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn delete_local_tag(&self, path: &str, tag: &str) -> CustomResult<()> {
+        let exists = Command::new("git")
+            .arg("rev-parse")
+            .arg("-q")
+            .arg("--verify")
+            .arg(format!("refs/tags/{}", tag))
+            .current_dir(path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?
+            .status
+            .success();
+        if !exists {
+            self.get_logger()
+                .warn(format!("Local tag {} not found in repo: {}", tag, self.repo.path).as_str());
+            return Ok(());
+        }
+        self.run_git(path, &["tag", "-d", tag], "Failed to delete local tag")?;
+
+        Ok(())
+    }
+
+    // A ref that is already gone counts as deleted, so a rollback can be run again
+    fn delete_remote_ref(&self, path: &str, reference: &str) -> CustomResult<()> {
+        let output = Command::new("git")
+            .arg("ls-remote")
+            .arg("--exit-code")
+            .arg("origin")
+            .arg(reference)
+            .current_dir(path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        match output.status.code() {
+            Some(0) => {}
+            Some(2) => {
+                self.get_logger().warn(
+                    format!(
+                        "Remote ref {} not found in repo: {}. Already deleted",
+                        reference, self.repo.path
+                    )
+                    .as_str(),
+                );
+                return Ok(());
+            }
+            _ => {
+                self.log_failure("Failed to check remote ref", &output);
+                return Err(CustomError::CommandExecution(
+                    "Failed to check remote ref".to_string(),
+                ));
+            }
+        }
+
+        let refspec = format!(":{}", reference);
+        let mut args = vec!["push", "origin", &refspec];
+        if self.disable_checks {
+            args.push("--no-verify");
+        }
+        self.run_git(path, &args, "Failed to delete remote ref")?;
+
+        Ok(())
+    }
+
+    fn revert_commit(&self, path: &str, commit: &str) -> CustomResult<()> {
+        self.run_git(
+            path,
+            &["revert", "--no-edit", commit],
+            "Failed to revert release commit",
+        )?;
+        self.push(path, &[])?;
+
+        Ok(())
+    }
+
+    // Only allowed while the release commit is still the tip of the remote branch.
+    fn reset_commit(&self, path: &str, commit: &str) -> CustomResult<()> {
+        let remote = format!("origin/{}", self.branch);
+        let output = self.run_git(
+            path,
+            &["rev-parse", &remote],
+            "Failed to resolve remote branch",
+        )?;
+        let tip = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if tip != commit {
+            return Err(CustomError::CommandExecution(format!(
+                "Release commit {} is not the tip of {}, use revert instead",
+                commit, remote
+            )));
+        }
+
+        let parent = format!("{}^", commit);
+        self.run_git(
+            path,
+            &["reset", "--hard", &parent],
+            "Failed to reset branch",
+        )?;
+        let lease = format!("--force-with-lease={}:{}", self.branch, commit);
+        self.push(path, &[&lease])?;
+
+        Ok(())
+    }
+
+    // Pushes HEAD, a worktree is detached and has no local branch to push
+    fn push(&self, path: &str, extra_args: &[&str]) -> CustomResult<()> {
+        let target = format!("HEAD:refs/heads/{}", self.branch);
+        let mut args = vec!["push", "origin", &target];
+        args.extend_from_slice(extra_args);
+        if self.disable_checks {
+            args.push("--no-verify");
+        }
+        self.run_git(path, &args, "Failed to push rollback")?;
+
+        Ok(())
+    }
+
+    fn close_pr(&self, path: &str, pr_id: &str) -> CustomResult<()> {
+        let command_string = format!(
+            r#"
+                {0}
+                aws codecommit update-pull-request-status --pull-request-id {1} --pull-request-status CLOSED
+            "#,
+            get_switch_role_command(self.sso_script_path, self.role),
+            pr_id
+        );
+        let output = Command::new("zsh")
+            .arg("-c")
+            .arg(&command_string)
+            .current_dir(path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            self.log_failure("Failed to close PR", &output);
+            return Err(CustomError::CommandExecution(
+                "Failed to close PR".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn run_git(&self, path: &str, args: &[&str], error: &str) -> CustomResult<Output> {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            self.log_failure(error, &output);
+            return Err(CustomError::CommandExecution(error.to_string()));
+        }
+
+        Ok(output)
+    }
+
+    fn log_failure(&self, error: &str, output: &Output) {
+        let logger = self.get_logger();
+        logger.error(format!("{} for repo: {}", error, self.repo.path).as_str());
+        logger.error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+    }
+}
+
+pub fn rollback(config: &Data, run_id: &str, preview: bool, reset: bool) -> CustomResult<()> {
+    let logger = Logger::new();
+    let report = RunReport::read(&config.report_dir, run_id)?;
+    let released: Vec<&RepoReport> = report
+        .repos
+        .iter()
+        .filter(|repo| has_release_artifacts(repo))
+        .collect();
+    logger.info(
+        format!(
+            "Rolling back run {}: {} repos with release artifacts",
+            report.run_id,
+            released.len()
+        )
+        .as_str(),
+    );
+
+    if !preview {
+        login(
+            &report.branch,
+            &config.aws.role_script_path,
+            &config.aws.role,
+            &config.aws.sso_start_url,
+        )?;
+    }
+
+    let worktree_root = get_worktree_root(config);
+    let mut errors: Vec<(String, String)> = Vec::new();
+    for repo in released {
+        let runner = RollbackRunner {
+            repo,
+            branch: &report.branch,
+            release_branch: &report.release_branch,
+            role: &config.aws.role,
+            sso_script_path: &config.aws.role_script_path,
+            disable_checks: config.disable_checks,
+            reset,
+            checkout_policy: config.checkout_policy,
+            run_id: &report.run_id,
+            worktree_root: worktree_root.as_ref(),
+        };
+
+        if preview {
+            logger.warn(get_preview(repo, &runner.get_steps()).as_str());
+            continue;
+        }
+
+        if let Err(e) = runner.rollback() {
+            errors.push((repo.name.clone(), e.to_string()));
+        }
+    }
+
+    if !errors.is_empty() {
+        logger.warn(format!("Rollback errors: {:#?}", errors).as_str());
+        let repos: Vec<String> = errors.into_iter().map(|(repo, _)| repo).collect();
+        return Err(CustomError::CommandExecution(format!(
+            "Rollback of run {} failed for repos: {}",
+            report.run_id,
+            repos.join(", ")
+        )));
+    }
+    logger.info(format!("Rollback of run {} finished", report.run_id).as_str());
+
+    Ok(())
+}
+
+fn get_preview(repo: &RepoReport, steps: &[RollbackStep]) -> String {
+    let mut lines = vec![format!("{} ({}):", repo.name, repo.path)];
+    lines.extend(steps.iter().map(|step| format!("  - {}", step)));
+    if let Some(back_merge) = &repo.back_merge {
+        lines.push(format!("  back-merge is not undone: {}", back_merge));
+    }
+
+    lines.join("\n")
+}
+
+// A repo that failed halfway can still have pushed its tag or commit
+fn has_release_artifacts(repo: &RepoReport) -> bool {
+    repo.tag.is_some()
+        || repo.release_commit.is_some()
+        || repo.pr_id.is_some()
+        || repo.release_branch_created_from.is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{commit_file, git, init_git_repo, TempDir};
+
+    fn get_runner<'a>(
+        repo: &'a RepoReport,
+        names: &'a [String; 5],
+        reset: bool,
+        worktree_root: Option<&'a PathBuf>,
+    ) -> RollbackRunner<'a> {
+        RollbackRunner {
+            repo,
+            branch: &names[0],
+            release_branch: &names[1],
+            role: &names[2],
+            sso_script_path: &names[3],
+            disable_checks: true,
+            reset,
+            checkout_policy: CheckoutPolicy::Refuse,
+            run_id: &names[4],
+            worktree_root,
+        }
+    }
+
+    fn get_names() -> [String; 5] {
+        [
+            String::from("main"),
+            String::from("release"),
+            String::new(),
+            String::new(),
+            String::from("20240102-100000"),
+        ]
+    }
+
+    #[test]
+    fn test_get_steps_follows_release_artifacts() {
+        let mut report = RunReport::new("main", "release");
+        let repo = report.add_repo("repo", "/repos/repo");
+        repo.tag = Some(String::from("release/1.0.1"));
+        repo.release_commit = Some(String::from("abc123"));
+        repo.pr_id = Some(String::from("42"));
+        repo.release_branch_created_from = Some(String::from("def456"));
+        let names = get_names();
+
+        let steps = get_runner(repo, &names, false, None)
+            .get_steps()
+            .iter()
+            .map(|step| step.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(
+            steps,
+            vec![
+                "delete local tag release/1.0.1",
+                "delete remote tag release/1.0.1",
+                "revert commit abc123",
+                "close PR 42",
+                "delete release branch release created by the run",
+            ]
+        );
+
+        let steps = get_runner(repo, &names, true, None).get_steps();
+        assert!(matches!(&steps[2], RollbackStep::ResetCommit(commit) if commit == "abc123"));
+    }
+
+    #[test]
+    fn test_get_steps_covers_tag_of_failed_release() {
+        let mut report = RunReport::new("main", "release");
+        let repo = report.add_repo("repo", "/repos/repo");
+        repo.tag = Some(String::from("release/1.0.1"));
+        let names = get_names();
+
+        let steps = get_runner(repo, &names, false, None).get_steps();
+
+        assert_eq!(steps.len(), 2);
+        assert!(has_release_artifacts(repo));
+    }
+
+    #[test]
+    fn test_get_preview_lists_steps_and_back_merge() {
+        let mut report = RunReport::new("main", "release");
+        let repo = report.add_repo("repo", "/repos/repo");
+        repo.release_commit = Some(String::from("abc123"));
+        repo.back_merge = Some(String::from("merged"));
+        let names = get_names();
+        let steps = get_runner(repo, &names, false, None).get_steps();
+
+        assert_eq!(
+            get_preview(repo, &steps),
+            "repo (/repos/repo):\n  - revert commit abc123\n  back-merge is not undone: merged"
+        );
+    }
+
+    #[test]
+    fn test_rollback_in_worktree_can_run_again() {
+        let dir = TempDir::new("rollback_worktree");
+        let work = init_git_repo(&dir);
+        let base = git(&work, &["rev-parse", "HEAD"]);
+        let commit = commit_file(&work, "package.json", "{}\n", "@1.0.1 release");
+        git(&work, &["tag", "release/1.0.1"]);
+        git(&work, &["push", "-q", "origin", "main", "release/1.0.1"]);
+        git(&work, &["tag", "release/1.0.2"]);
+        let worktree_root = dir.join("worktrees");
+        let names = get_names();
+
+        let mut report = RunReport::new("main", "release");
+        let repo = report.add_repo("repo", &work.to_string_lossy());
+        repo.tag = Some(String::from("release/1.0.1"));
+        repo.release_commit = Some(commit.clone());
+        get_runner(repo, &names, true, Some(&worktree_root))
+            .rollback()
+            .unwrap();
+
+        assert_eq!(
+            git(&work, &["ls-remote", "origin", "main"]),
+            format!("{}\trefs/heads/main", base)
+        );
+        assert_eq!(git(&work, &["ls-remote", "--tags", "origin"]), "");
+        assert_eq!(git(&work, &["tag", "-l", "release/1.0.1"]), "");
+        assert_eq!(git(&work, &["rev-parse", "HEAD"]), commit);
+        assert!(!worktree_root.join("repo-rollback-20240102-100000").exists());
+
+        // The tag of a failed release was created but never pushed
+        repo.tag = Some(String::from("release/1.0.2"));
+        repo.release_commit = None;
+        let runner = get_runner(repo, &names, true, Some(&worktree_root));
+        runner.rollback().unwrap();
+        runner.rollback().unwrap();
+        assert_eq!(git(&work, &["tag", "-l"]), "");
+    }
+}
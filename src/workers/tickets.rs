@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    config::TicketsConfig,
+    custom_error::{CustomError, CustomResult},
+    workers::{
+        history::Commit,
+        release_notes::{group_by_type, CommitTypeGroup},
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TicketGroup {
+    // `None` holds the commits that don't reference any ticket
+    pub key: Option<String>,
+    pub link: Option<String>,
+    pub types: Vec<CommitTypeGroup>,
+}
+
+pub struct TicketExtractor<'config> {
+    patterns: Vec<Regex>,
+    url_template: Option<&'config String>,
+}
+
+impl<'config> TicketExtractor<'config> {
+    pub fn new(config: &'config TicketsConfig) -> CustomResult<Self> {
+        let patterns = config
+            .patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|err| {
+                    CustomError::ConfigParsingError(format!(
+                        "Invalid ticket pattern `{}`: {}",
+                        pattern, err
+                    ))
+                })
+            })
+            .collect::<CustomResult<Vec<Regex>>>()?;
+
+        Ok(Self {
+            patterns,
+            url_template: config.url_template.as_ref(),
+        })
+    }
+
+    // Looks for ticket keys in the given texts. Patterns with a capture group use the
+    // first group as the key.
+    fn extract(&self, texts: &[&str]) -> Vec<String> {
+        let mut keys: Vec<String> = Vec::new();
+        for text in texts {
+            for pattern in self.patterns.iter() {
+                for captures in pattern.captures_iter(text) {
+                    let key = captures
+                        .get(1)
+                        .or_else(|| captures.get(0))
+                        .map(|key| key.as_str().to_string())
+                        .unwrap_or_default();
+                    if !key.is_empty() && !keys.contains(&key) {
+                        keys.push(key);
+                    }
+                }
+            }
+        }
+
+        keys
+    }
+
+    // Groups commits and extra notes by ticket in order of first appearance, then by
+    // commit type. A commit referencing several tickets is listed under each of them.
+    // `merge_subjects` holds the subjects of the merges that brought each commit in, keyed
+    // by commit hash, so a key that is only in the branch name is found too.
+    pub fn group(
+        &self,
+        commits: &[Commit],
+        merge_subjects: &HashMap<String, Vec<String>>,
+        notes: &[String],
+    ) -> Vec<TicketGroup> {
+        let entries = commits
+            .iter()
+            .map(|commit| {
+                let mut texts = vec![commit.subject.as_str(), commit.body.as_str()];
+                if let Some(subjects) = merge_subjects.get(&commit.hash) {
+                    texts.extend(subjects.iter().map(String::as_str));
+                }
+                texts
+            })
+            .chain(notes.iter().map(|note| vec![note.as_str()]));
+
+        let mut tickets: Vec<(Option<String>, Vec<&str>)> = Vec::new();
+        let mut unticketed: Vec<&str> = Vec::new();
+        for texts in entries {
+            let subject = texts[0];
+            let keys = self.extract(&texts);
+            if keys.is_empty() {
+                unticketed.push(subject);
+                continue;
+            }
+            for key in keys {
+                match tickets
+                    .iter_mut()
+                    .find(|(existing, _)| existing.as_ref() == Some(&key))
+                {
+                    Some((_, subjects)) => subjects.push(subject),
+                    None => tickets.push((Some(key), vec![subject])),
+                }
+            }
+        }
+        if !unticketed.is_empty() {
+            tickets.push((None, unticketed));
+        }
+
+        tickets
+            .into_iter()
+            .map(|(key, subjects)| TicketGroup {
+                link: key.as_ref().and_then(|key| self.get_link(key)),
+                key,
+                types: group_by_type(&subjects),
+            })
+            .collect()
+    }
+
+    fn get_link(&self, key: &str) -> Option<String> {
+        self.url_template
+            .map(|template| template.replace("{ticket}", key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workers::{history::parse_git_log, release_notes::render_changes};
+
+    fn get_commits(subjects: &[(&str, &str)]) -> Vec<Commit> {
+        let log: String = subjects
+            .iter()
+            .enumerate()
+            .map(|(index, (subject, body))| {
+                format!(
+                    "c{0}\u{1f}p{0}\u{1f}Jane\u{1f}jane@example.com\u{1f}2024-01-01T10:00:00+00:00\u{1f}{1}\u{1f}{2}\u{1f}\u{1e}\n",
+                    index, subject, body
+                )
+            })
+            .collect();
+        parse_git_log(&log).unwrap()
+    }
+
+    fn get_config(patterns: &[&str]) -> TicketsConfig {
+        TicketsConfig {
+            enabled: true,
+            patterns: patterns.iter().map(|pattern| pattern.to_string()).collect(),
+            url_template: Some("https://jira.example.com/browse/{ticket}".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_extract_reads_subject_body_and_merged_branch() {
+        let config = get_config(&[r"\b(CONF-\d+)\b"]);
+        let extractor = TicketExtractor::new(&config).unwrap();
+        let merge = extractor.extract(&["Merge branch 'feature/CONF-12-export' into dev", ""]);
+        let commit = extractor.extract(&["fix: handle nulls CONF-7", "Refs: CONF-8, CONF-7"]);
+
+        assert_eq!(merge, vec!["CONF-12"]);
+        assert_eq!(commit, vec!["CONF-7", "CONF-8"]);
+    }
+
+    #[test]
+    fn test_group_by_ticket_and_type() {
+        let config = get_config(&[r"\bCONF-\d+\b"]);
+        let extractor = TicketExtractor::new(&config).unwrap();
+        let commits = get_commits(&[
+            ("chore: CONF-1 bump deps", ""),
+            ("update readme", ""),
+            ("fix(api): CONF-1 nulls", ""),
+            ("feat: CONF-1 export", ""),
+        ]);
+        let notes = vec![String::from("chore(deps): bump shared to 1.0.1")];
+
+        let groups = extractor.group(&commits, &HashMap::new(), &notes);
+
+        assert_eq!(
+            render_changes(&[], Some(&groups)),
+            "CONF-1 (https://jira.example.com/browse/CONF-1)\n  feat\n    - feat: CONF-1 export\n  fix\n    - fix(api): CONF-1 nulls\n  chore\n    - chore: CONF-1 bump deps\nNo ticket\n  chore\n    - chore(deps): bump shared to 1.0.1\n  other\n    - update readme\n"
+        );
+    }
+
+    #[test]
+    fn test_group_reads_keys_from_merge_subjects() {
+        let config = get_config(&[r"\b(CONF-\d+)\b"]);
+        let extractor = TicketExtractor::new(&config).unwrap();
+        let commits = get_commits(&[("feat: export", ""), ("fix: typo", "")]);
+        let merge_subjects = HashMap::from([(
+            String::from("c0"),
+            vec![String::from(
+                "Merge branch 'feature/CONF-12-export' into main",
+            )],
+        )]);
+
+        let groups = extractor.group(&commits, &merge_subjects, &[]);
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].key.as_deref(), Some("CONF-12"));
+        assert_eq!(groups[1].key, None);
+    }
+
+    #[test]
+    fn test_invalid_pattern_is_a_config_error() {
+        let config = get_config(&["CONF-("]);
+
+        assert!(matches!(
+            TicketExtractor::new(&config),
+            Err(CustomError::ConfigParsingError(_))
+        ));
+    }
+}
@@ -0,0 +1,579 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use glob::{MatchOptions, Pattern};
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    command_log::LoggedCommand,
+    config::{PythonConfig, RepoSettings, RepoType, VerifyConfig},
+    custom_error::{CustomError, CustomResult},
+    logger::LoggerTrait,
+    workers::python::PythonRebuilder,
+};
+
+const PYTHON_WHEEL_SCRIPT: &str = r#""$1" -m pip wheel --no-deps --quiet --wheel-dir "$2" ."#;
+const PYTHON_LIST_SCRIPT: &str = r#""$1" -c 'import sys, zipfile; print("\n".join(zipfile.ZipFile(sys.argv[1]).namelist()))' "$2""#;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PackageInfo {
+    pub name: String,
+    pub version: String,
+    // Size of the tarball or wheel in bytes
+    pub size: u64,
+    // Size of the last released package of the repo
+    pub previous_size: Option<u64>,
+    pub files: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct NpmPackEntry {
+    name: String,
+    version: String,
+    size: u64,
+    files: Vec<NpmPackFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NpmPackFile {
+    path: String,
+}
+
+struct Artifact {
+    name: String,
+    version: String,
+    size: u64,
+    files: Vec<String>,
+}
+
+// Builds the package of a patched repo without publishing it and checks that it
+// is what the release is supposed to publish
+pub struct PackageVerifier<'repo> {
+    pub repo_name: &'repo String,
+    pub repo: &'repo String,
+    pub repo_type: RepoType,
+    // Paths or globs inside the package
+    pub required_files: &'repo [String],
+    pub python: &'repo PythonConfig,
+    pub config: &'repo VerifyConfig,
+}
+
+impl LoggerTrait for PackageVerifier<'_> {}
+impl PackageVerifier<'_> {
+    pub fn verify(&self, next_version: &str) -> CustomResult<PackageInfo> {
+        let logger = self.get_logger();
+        logger.info(format!("Verifying package of repo: {}", self.repo).as_str());
+        let artifact = match self.repo_type {
+            RepoType::Node => self.pack_node()?,
+            RepoType::Python => self.pack_python()?,
+        };
+        let previous = self.read_last_package()?;
+        let info = PackageInfo {
+            name: artifact.name.clone(),
+            version: artifact.version.clone(),
+            size: artifact.size,
+            previous_size: previous.map(|previous| previous.size),
+            files: artifact.files.len(),
+        };
+
+        let mut problems = Vec::new();
+        if let Some(expected) = self.get_expected_name()? {
+            if normalize_name(&artifact.name, self.repo_type)
+                != normalize_name(&expected, self.repo_type)
+            {
+                problems.push(format!(
+                    "package name is {}, expected {}",
+                    artifact.name, expected
+                ));
+            }
+        }
+        if !is_same_version(&artifact.version, next_version, self.repo_type) {
+            problems.push(format!(
+                "package version is {}, expected {}",
+                artifact.version, next_version
+            ));
+        }
+        let missing = get_missing_files(&artifact.files, self.required_files)?;
+        if !missing.is_empty() {
+            problems.push(format!("package is missing {}", missing.join(", ")));
+        }
+        if let Some(problem) = self.check_growth(&info) {
+            problems.push(problem);
+        }
+        if !problems.is_empty() {
+            logger.error(
+                format!(
+                    "Package verification failed in repo: {}. {}",
+                    self.repo,
+                    problems.join("; ")
+                )
+                .as_str(),
+            );
+            return Err(CustomError::PackageVerification(problems.join("; ")));
+        }
+
+        logger.info(
+            format!(
+                "Verified package of repo: {}. {}@{}, {} bytes",
+                self.repo, info.name, info.version, info.size
+            )
+            .as_str(),
+        );
+
+        Ok(info)
+    }
+
+    fn pack_node(&self) -> CustomResult<Artifact> {
+        let output = Command::new("npm")
+            .arg("pack")
+            .arg("--dry-run")
+            .arg("--json")
+            .arg("--ignore-scripts")
+            .current_dir(self.repo)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        self.check_output(&output, "Failed to pack node repo")?;
+
+        let entries: Vec<NpmPackEntry> = serde_json::from_slice(&output.stdout).map_err(|err| {
+            CustomError::PackageVerification(format!("Unexpected npm pack output: {}", err))
+        })?;
+        let entry = entries.into_iter().next().ok_or_else(|| {
+            CustomError::PackageVerification("npm pack did not list a package".to_string())
+        })?;
+
+        Ok(Artifact {
+            name: entry.name,
+            version: entry.version,
+            size: entry.size,
+            files: entry.files.into_iter().map(|file| file.path).collect(),
+        })
+    }
+
+    // Builds a wheel in the virtualenv of the rebuild, if there is one
+    fn pack_python(&self) -> CustomResult<Artifact> {
+        let dir = std::env::temp_dir()
+            .join("version_updater")
+            .join("packages")
+            .join(self.repo_name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).map_err(|err| CustomError::FileSystem(err.to_string()))?;
+
+        let mut command = self.get_python_command(PYTHON_WHEEL_SCRIPT)?;
+        let output = command
+            .arg(&self.python.interpreter)
+            .arg(&dir)
+            .current_dir(self.repo)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        self.check_output(&output, "Failed to build wheel")?;
+
+        let wheel = get_wheel(&dir)?;
+        let mut command = self.get_python_command(PYTHON_LIST_SCRIPT)?;
+        let output = command
+            .arg(&self.python.interpreter)
+            .arg(&wheel)
+            .current_dir(self.repo)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        self.check_output(&output, "Failed to list wheel files")?;
+
+        // `{name}-{version}(-{build})?-{python}-{abi}-{platform}.whl`
+        let file_name = wheel
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut parts = file_name.split('-');
+        let (Some(name), Some(version)) = (parts.next(), parts.next()) else {
+            return Err(CustomError::PackageVerification(format!(
+                "Unexpected wheel name: {}",
+                file_name
+            )));
+        };
+        let size = fs::metadata(&wheel)
+            .map_err(|err| CustomError::FileSystem(err.to_string()))?
+            .len();
+
+        Ok(Artifact {
+            name: name.to_string(),
+            version: version.to_string(),
+            size,
+            files: String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(String::from)
+                .collect(),
+        })
+    }
+
+    fn get_python_command(&self, script: &str) -> CustomResult<Command> {
+        let mut command = PythonRebuilder {
+            repo_name: self.repo_name,
+            repo: self.repo,
+            config: self.python,
+        }
+        .get_shell_command(script)?;
+        // `$0` of the script
+        command.arg("verify");
+
+        Ok(command)
+    }
+
+    // The name the version was bumped for. npm pack reads the same package.json the
+    // patch touched, so node repos are checked against the name from before the patch.
+    // Repos without a known name are not checked.
+    fn get_expected_name(&self) -> CustomResult<Option<String>> {
+        if let RepoType::Node = self.repo_type {
+            return self.get_unpatched_node_name();
+        }
+
+        let path = Path::new(self.repo).join("pyproject.toml");
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(CustomError::FileSystem(err.to_string())),
+        };
+        let manifest: toml::Value = toml::from_str(&content).map_err(|err| {
+            CustomError::FileSystem(format!("Could not parse {}: {}", path.display(), err))
+        })?;
+        let keys: [&[&str]; 2] = [&["project", "name"], &["tool", "poetry", "name"]];
+
+        Ok(keys.iter().find_map(|keys| {
+            keys.iter()
+                .try_fold(&manifest, |value, key| value.get(key))
+                .and_then(|name| name.as_str())
+                .map(String::from)
+        }))
+    }
+
+    // Verification runs on the release commit, its parent has the package.json from before the patch
+    fn get_unpatched_node_name(&self) -> CustomResult<Option<String>> {
+        let output = Command::new("git")
+            .arg("show")
+            .arg("HEAD^:package.json")
+            .current_dir(self.repo)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            self.get_logger().debug(
+                format!(
+                    "No package.json before the release commit in repo: {}",
+                    self.repo
+                )
+                .as_str(),
+            );
+            return Ok(None);
+        }
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&output.stdout).map_err(|err| {
+                CustomError::PackageVerification(format!(
+                    "Could not parse package.json before the release commit: {}",
+                    err
+                ))
+            })?;
+
+        Ok(manifest
+            .get("name")
+            .and_then(|name| name.as_str())
+            .map(String::from))
+    }
+
+    fn check_growth(&self, info: &PackageInfo) -> Option<String> {
+        let max_growth_percent = self.config.max_growth_percent?;
+        let previous_size = info.previous_size.filter(|size| *size > 0)?;
+        let growth_percent =
+            (info.size as f64 - previous_size as f64) / previous_size as f64 * 100.0;
+        if growth_percent <= max_growth_percent {
+            return None;
+        }
+
+        Some(format!(
+            "package grew by {:.1}% from {} to {} bytes, allowed {}%",
+            growth_percent, previous_size, info.size, max_growth_percent
+        ))
+    }
+
+    fn get_package_path(&self) -> PathBuf {
+        Path::new(&self.config.dir).join(format!("{}.json", self.repo_name))
+    }
+
+    fn read_last_package(&self) -> CustomResult<Option<PackageInfo>> {
+        let content = match fs::read_to_string(self.get_package_path()) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(CustomError::FileSystem(err.to_string())),
+        };
+
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|err| CustomError::FileSystem(err.to_string()))
+    }
+
+    // Only published packages are the baseline of the growth check
+    pub fn store_package(&self, info: &PackageInfo) -> CustomResult<()> {
+        fs::create_dir_all(&self.config.dir)
+            .map_err(|err| CustomError::FileSystem(err.to_string()))?;
+        let content = serde_json::to_string_pretty(info)
+            .map_err(|err| CustomError::FileSystem(err.to_string()))?;
+        fs::write(self.get_package_path(), content)
+            .map_err(|err| CustomError::FileSystem(err.to_string()))
+    }
+
+    fn check_output(&self, output: &std::process::Output, error: &str) -> CustomResult<()> {
+        if !output.status.success() {
+            let logger = self.get_logger();
+            logger.error(format!("{} in repo: {}", error, self.repo).as_str());
+            logger.error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+
+            return Err(CustomError::CommandExecution(format!("{} in repo", error)));
+        }
+
+        Ok(())
+    }
+}
+
+// Repo settings win over the files of the ecosystem
+pub fn get_required_files(
+    config: &VerifyConfig,
+    repo_type: RepoType,
+    settings: Option<&RepoSettings>,
+) -> Vec<String> {
+    if let Some(files) = settings.and_then(|settings| settings.package_files.as_ref()) {
+        return files.clone();
+    }
+    match repo_type {
+        RepoType::Node => config.node_files.clone(),
+        RepoType::Python => config.python_files.clone(),
+    }
+}
+
+fn get_wheel(dir: &Path) -> CustomResult<PathBuf> {
+    fs::read_dir(dir)
+        .map_err(|err| CustomError::FileSystem(err.to_string()))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| path.extension().is_some_and(|extension| extension == "whl"))
+        .ok_or_else(|| {
+            CustomError::PackageVerification(format!("No wheel was built in {}", dir.display()))
+        })
+}
+
+fn get_missing_files(files: &[String], required: &[String]) -> CustomResult<Vec<String>> {
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    let mut missing = Vec::new();
+    for file in required {
+        let pattern = Pattern::new(file).map_err(|err| {
+            CustomError::ConfigParsingError(format!("Invalid package file `{}`: {}", file, err))
+        })?;
+        if !files.iter().any(|path| pattern.matches_with(path, options)) {
+            missing.push(file.clone());
+        }
+    }
+
+    Ok(missing)
+}
+
+// Wheels spell `my-package` as `my_package` and PyPI ignores the case
+fn normalize_name(name: &str, repo_type: RepoType) -> String {
+    match repo_type {
+        RepoType::Node => name.to_string(),
+        RepoType::Python => name.to_lowercase().replace(['-', '.'], "_"),
+    }
+}
+
+// Python tools normalize PEP 440 versions, `5.8.19a` is built as `5.8.19a0` and
+// `5.9.25c` as `5.9.25rc0`. Other versions, like `5.9.25d` or `5.9.25aa`, are kept as
+// they are by setuptools, so they have to match as written.
+fn is_same_version(version: &str, expected: &str, repo_type: RepoType) -> bool {
+    match repo_type {
+        RepoType::Node => version == expected,
+        RepoType::Python => {
+            match (
+                normalize_python_version(version),
+                normalize_python_version(expected),
+            ) {
+                (Some(version), Some(expected)) => version == expected,
+                _ => version.trim().to_lowercase() == expected.trim().to_lowercase(),
+            }
+        }
+    }
+}
+
+// Canonical form of a PEP 440 version for comparison, `None` when it is not one.
+// Trailing zeros of the release are dropped, `1.0` and `1.0.0` are the same version.
+fn normalize_python_version(version: &str) -> Option<String> {
+    let pattern = Regex::new(
+        r"(?ix)^\s*v?
+        (?:(?P<epoch>[0-9]+)!)?
+        (?P<release>[0-9]+(?:\.[0-9]+)*)
+        (?:[-_.]?(?P<pre_l>alpha|a|beta|b|preview|pre|rc|c)[-_.]?(?P<pre_n>[0-9]+)?)?
+        (?:-(?P<post_n1>[0-9]+)|[-_.]?(?P<post_l>post|rev|r)[-_.]?(?P<post_n2>[0-9]+)?)?
+        (?:[-_.]?(?P<dev_l>dev)[-_.]?(?P<dev_n>[0-9]+)?)?
+        (?:\+(?P<local>[a-z0-9]+(?:[-_.][a-z0-9]+)*))?
+        \s*$",
+    )
+    .ok()?;
+    let captures = pattern.captures(version)?;
+    let number = |name: &str| {
+        captures
+            .name(name)
+            .and_then(|value| value.as_str().parse::<u64>().ok())
+            .unwrap_or(0)
+    };
+
+    let mut normalized = String::new();
+    if number("epoch") > 0 {
+        normalized.push_str(&format!("{}!", number("epoch")));
+    }
+    let mut release: Vec<u64> = captures["release"]
+        .split('.')
+        .map(|part| part.parse::<u64>().unwrap_or(0))
+        .collect();
+    while release.len() > 1 && release.last() == Some(&0) {
+        release.pop();
+    }
+    let release: Vec<String> = release.iter().map(u64::to_string).collect();
+    normalized.push_str(&release.join("."));
+    if let Some(pre) = captures.name("pre_l") {
+        let pre = match pre.as_str().to_lowercase().as_str() {
+            "alpha" | "a" => "a",
+            "beta" | "b" => "b",
+            _ => "rc",
+        };
+        normalized.push_str(&format!("{}{}", pre, number("pre_n")));
+    }
+    if captures.name("post_n1").is_some() || captures.name("post_l").is_some() {
+        normalized.push_str(&format!(
+            ".post{}",
+            number("post_n1").max(number("post_n2"))
+        ));
+    }
+    if captures.name("dev_l").is_some() {
+        normalized.push_str(&format!(".dev{}", number("dev_n")));
+    }
+    if let Some(local) = captures.name("local") {
+        normalized.push_str(&format!(
+            "+{}",
+            local.as_str().to_lowercase().replace(['-', '_'], ".")
+        ));
+    }
+
+    Some(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{commit_file, init_git_repo, TempDir};
+
+    #[test]
+    fn test_get_missing_files_matches_paths_and_globs() {
+        let files = vec![
+            "package.json".to_string(),
+            "dist/index.js".to_string(),
+            "dist/types/index.d.ts".to_string(),
+        ];
+        let required = vec![
+            "package.json".to_string(),
+            "dist/*.js".to_string(),
+            "dist/*.d.ts".to_string(),
+            "README.md".to_string(),
+        ];
+
+        assert_eq!(
+            get_missing_files(&files, &required).unwrap(),
+            vec!["dist/*.d.ts", "README.md"]
+        );
+    }
+
+    #[test]
+    fn test_python_names_and_versions_are_normalized() {
+        assert_eq!(
+            normalize_name("Conform-Custom.Code", RepoType::Python),
+            "conform_custom_code"
+        );
+        assert!(is_same_version("5.8.19a0", "5.8.19a", RepoType::Python));
+        assert!(!is_same_version("5.8.19", "5.8.20", RepoType::Python));
+        assert!(!is_same_version("5.8.19a0", "5.8.19a", RepoType::Node));
+    }
+
+    #[test]
+    fn test_python_letter_versions_are_compared_like_the_build() {
+        // `c` is another spelling of `rc`
+        assert!(is_same_version("5.9.25rc0", "5.9.25c", RepoType::Python));
+        assert!(!is_same_version("5.9.25c0", "5.9.25b", RepoType::Python));
+        // Not PEP 440, built as written
+        assert_eq!(normalize_python_version("5.9.25d"), None);
+        assert_eq!(normalize_python_version("5.9.25aa"), None);
+        assert!(is_same_version("5.9.25d", "5.9.25d", RepoType::Python));
+        assert!(!is_same_version("5.9.25d0", "5.9.25d", RepoType::Python));
+        assert!(is_same_version("5.9.25aa", "5.9.25AA", RepoType::Python));
+        assert!(!is_same_version("5.9.25a0", "5.9.25aa", RepoType::Python));
+    }
+
+    #[test]
+    fn test_normalize_python_version_follows_pep_440() {
+        let cases = [
+            ("v1.0", "1"),
+            ("1!2.0.0-Alpha.1", "1!2a1"),
+            ("1.2beta", "1.2b0"),
+            ("1.2-preview3", "1.2rc3"),
+            ("1.2-1", "1.2.post1"),
+            ("1.2.rev", "1.2.post0"),
+            ("1.2-dev", "1.2.dev0"),
+            ("1.2+Ubuntu-1", "1.2+ubuntu.1"),
+        ];
+        for (version, normalized) in cases {
+            assert_eq!(
+                normalize_python_version(version).as_deref(),
+                Some(normalized),
+                "{}",
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_expected_name_reads_node_name_before_release_commit() {
+        let dir = TempDir::new("verifier_expected_name");
+        let work = init_git_repo(&dir);
+        commit_file(
+            &work,
+            "package.json",
+            "{\"name\": \"shared\"}\n",
+            "feat: package",
+        );
+        commit_file(
+            &work,
+            "package.json",
+            "{\"name\": \"renamed\"}\n",
+            "@1.0.1 release",
+        );
+        let repo_name = String::from("shared");
+        let repo = work.to_string_lossy().to_string();
+        let python = PythonConfig::default();
+        let config = VerifyConfig {
+            enabled: true,
+            node_files: Vec::new(),
+            python_files: Vec::new(),
+            max_growth_percent: None,
+            dir: dir.join("packages").to_string_lossy().to_string(),
+        };
+        let verifier = PackageVerifier {
+            repo_name: &repo_name,
+            repo: &repo,
+            repo_type: RepoType::Node,
+            required_files: &[],
+            python: &python,
+            config: &config,
+        };
+
+        assert_eq!(
+            verifier.get_expected_name().unwrap().as_deref(),
+            Some("shared")
+        );
+    }
+}
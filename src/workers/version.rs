@@ -100,9 +100,9 @@ impl VersionSelecter<'_> {
             Ok(format!("{current_version}{next_letter}"))
         } else if last == 'z' {
             current_version.pop();
-            return Ok(format!("{}aa", current_version));
+            Ok(format!("{}aa", current_version))
         } else {
-            return Ok(format!("{}a", current_version));
+            Ok(format!("{}a", current_version))
         }
     }
 
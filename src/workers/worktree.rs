@@ -0,0 +1,136 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use crate::{
+    command_log::LoggedCommand,
+    config::Data,
+    custom_error::{CustomError, CustomResult},
+    logger::LoggerTrait,
+};
+
+// Runs a release in a detached `git worktree` so the developer's checkout is never touched.
+pub struct WorktreeManager<'repo> {
+    pub repo_path: &'repo String,
+    pub branch: &'repo String,
+}
+
+impl LoggerTrait for WorktreeManager<'_> {}
+impl WorktreeManager<'_> {
+    pub fn create(&self, worktree_path: &Path) -> CustomResult<String> {
+        let logger = self.get_logger();
+        let path = worktree_path.to_string_lossy().to_string();
+        logger.info(format!("Creating worktree for repo: {} at {}", self.repo_path, path).as_str());
+
+        self.run_git(&["fetch", "origin"], "Failed to fetch repo")?;
+        if worktree_path.exists() {
+            self.remove(&path)?;
+        }
+        let target = format!("origin/{}", self.branch);
+        self.run_git(
+            &["worktree", "add", "--detach", &path, &target],
+            "Failed to create worktree for repo",
+        )?;
+        logger.info(format!("Created worktree: {}", path).as_str());
+
+        Ok(path)
+    }
+
+    pub fn remove(&self, worktree_path: &str) -> CustomResult<()> {
+        let logger = self.get_logger();
+        logger.info(format!("Removing worktree: {}", worktree_path).as_str());
+        self.run_git(
+            &["worktree", "remove", "--force", worktree_path],
+            "Failed to remove worktree for repo",
+        )?;
+        self.run_git(&["worktree", "prune"], "Failed to prune worktrees for repo")?;
+        logger.info(format!("Removed worktree: {}", worktree_path).as_str());
+
+        Ok(())
+    }
+
+    fn run_git(&self, args: &[&str], error: &str) -> CustomResult<Output> {
+        let logger = self.get_logger();
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(self.repo_path)
+            .logged_output()
+            .map_err(|err| CustomError::CommandExecution(err.to_string()))?;
+        if !output.status.success() {
+            logger.error(format!("{}: {}", error, self.repo_path).as_str());
+            logger.error(format!("Error: {}", String::from_utf8_lossy(&output.stderr)).as_str());
+
+            return Err(CustomError::CommandExecution(error.to_string()));
+        }
+
+        Ok(output)
+    }
+}
+
+impl<'repo> WorktreeManager<'repo> {
+    // Like `create`, but the worktree is removed again once the guard is dropped
+    pub fn create_guard(self, worktree_path: &Path) -> CustomResult<WorktreeGuard<'repo>> {
+        let path = self.create(worktree_path)?;
+
+        Ok(WorktreeGuard {
+            manager: self,
+            path,
+        })
+    }
+}
+
+// Removes the worktree on drop, so every way out of a repo's release cleans it up
+pub struct WorktreeGuard<'repo> {
+    manager: WorktreeManager<'repo>,
+    pub path: String,
+}
+
+impl Drop for WorktreeGuard<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.manager.remove(&self.path) {
+            self.manager
+                .get_logger()
+                .error(format!("Failed to remove worktree: {}. {}", self.path, e).as_str());
+        }
+    }
+}
+
+// Folder the worktrees are created in, `None` when worktree mode is disabled
+pub fn get_worktree_root(config: &Data) -> Option<PathBuf> {
+    config
+        .worktree
+        .as_ref()
+        .filter(|worktree| worktree.enabled)
+        .map(|worktree| match &worktree.root {
+            Some(root) => PathBuf::from(root),
+            None => std::env::temp_dir().join("version_updater"),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{git, init_git_repo, TempDir};
+
+    #[test]
+    fn test_guard_removes_worktree_on_drop() {
+        let dir = TempDir::new("worktree_guard");
+        let work = init_git_repo(&dir);
+        let repo_path = work.to_string_lossy().to_string();
+        let branch = String::from("main");
+        let worktree_path = dir.join("worktrees").join("repo-run");
+
+        let result: CustomResult<()> = (|| {
+            let manager = WorktreeManager {
+                repo_path: &repo_path,
+                branch: &branch,
+            };
+            let guard = manager.create_guard(&worktree_path)?;
+            assert!(Path::new(&guard.path).join("README.md").exists());
+            Err(CustomError::Cancelled(String::from("Run cancelled")))
+        })();
+
+        assert!(result.is_err());
+        assert!(!worktree_path.exists());
+        assert_eq!(git(&work, &["worktree", "list"]).lines().count(), 1);
+    }
+}